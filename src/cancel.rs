@@ -0,0 +1,35 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// Stops a running load test early: no new requests are started, requests in flight finish,
+/// and the report covers what completed. Clones share the same state, so one can be kept,
+/// e.g. by a Ctrl-C handler, while another is passed to the test.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<(AtomicBool, Notify)>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.inner.0.store(true, Ordering::SeqCst);
+        self.inner.1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the token is cancelled, right away if it already is
+    pub async fn cancelled(&self) {
+        // Registered before the check, so a cancel in between still wakes it
+        let notified = self.inner.1.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
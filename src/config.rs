@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, net::SocketAddr, path::Path, time::Duration};
 
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::websocket::is_websocket_url;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum HttpMethod {
@@ -10,20 +13,781 @@ pub enum HttpMethod {
     Put,
     Patch,
     Delete,
+    Head,
+    Options,
 }
 
+// Either one method for every request or a weighted mix, picked per request
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct LoadTestConfig {
+#[serde(untagged)]
+pub enum MethodMix {
+    Single(HttpMethod),
+    Weighted(Vec<WeightedMethod>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WeightedMethod {
+    pub method: HttpMethod,
+    pub weight: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Auth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataOrder {
+    #[default]
+    Sequential,
+    Random,
+    Shuffle,
+}
+
+// One file of data_files, either only its path or also how its rows are picked and named
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum DataFile {
+    Path(String),
+    Options {
+        path: String,
+        order: Option<DataOrder>, // data_order when omitted
+        prefix: Option<String>, // Columns become prefix.column instead of overwriting earlier files'
+    },
+}
+
+impl DataFile {
+    pub fn path(&self) -> &str {
+        match self {
+            DataFile::Path(path) | DataFile::Options { path, .. } => path,
+        }
+    }
+
+    pub fn order(&self) -> Option<DataOrder> {
+        match self {
+            DataFile::Path(_) => None,
+            DataFile::Options { order, .. } => *order,
+        }
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        match self {
+            DataFile::Path(_) => None,
+            DataFile::Options { prefix, .. } => prefix.as_deref(),
+        }
+    }
+}
+
+// Whether every request takes the next row or each worker keeps one row for its lifetime
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataAffinity {
+    #[default]
+    PerRequest,
+    PerWorker,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyType {
+    #[default]
+    Raw,
+    Form,
+    Json,
+    Multipart,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    Http1,
+    Http2,
+    #[default]
+    Auto,
+}
+
+// Pause between requests, either fixed, uniformly random within a range, or drawn from a
+// distribution
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ThinkTime {
+    Fixed(u64),
+    Range { min: u64, max: u64 },
+    Distribution { distribution: ThinkTimeDistribution },
+}
+
+// Heavy-tailed or bell-shaped pauses, closer to how real users wait than a uniform range
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ThinkTimeDistribution {
+    Exponential { mean_ms: u64 },
+    Normal { mean_ms: u64, stddev_ms: u64 },
+}
+
+impl ThinkTime {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match *self {
+            ThinkTime::Fixed(ms) => Duration::from_millis(ms),
+            ThinkTime::Range { min, max } => Duration::from_millis(rng.gen_range(min..=max)),
+            ThinkTime::Distribution { ref distribution } => {
+                // Negative normal samples are clamped, a pause can't be shorter than none
+                Duration::from_secs_f64(distribution.sample_ms(rng).max(0.0) / 1000.0)
+            }
+        }
+    }
+}
+
+impl ThinkTimeDistribution {
+    fn sample_ms(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            // Inverse transform of a uniform sample in [0, 1)
+            ThinkTimeDistribution::Exponential { mean_ms } => {
+                -(mean_ms as f64) * (1.0 - rng.gen::<f64>()).ln()
+            }
+            // Box-Muller transform, the first factor's sample must not be 0
+            ThinkTimeDistribution::Normal { mean_ms, stddev_ms } => {
+                let u1 = 1.0 - rng.gen::<f64>();
+                let u2 = rng.gen::<f64>();
+                let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                mean_ms as f64 + stddev_ms as f64 * z
+            }
+        }
+    }
+}
+
+// One weighted endpoint of a multi-endpoint load test
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Scenario {
+    pub name: Option<String>,
     pub url: String,
     pub method: Option<HttpMethod>,
-    pub request_count: usize,
     pub headers: Option<HashMap<String, String>>,
-    pub body: Option<String>, // Static body or template for dynamic body
-    pub data_file: Option<String>, // Path to your Excel/CSV file
+    pub body: Option<String>,
+    pub weight: u32,
+    pub rate_limit_rps: Option<u32>, // Own cap on top of the global rate_limit_rps
+}
+
+impl Scenario {
+    // Name used in the report, the URL when no name is given
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.url.clone())
+    }
+}
+
+// One phase of a staged load profile
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Stage {
+    pub duration_secs: u64,
+    pub target_concurrency: usize, // Reached linearly by the end of the stage
+}
+
+// Target request rate at a point of a traffic profile
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RatePoint {
+    pub offset_secs: u64, // Seconds since the start of the test
+    pub rps: u32,         // The rate changes linearly towards the next point
+}
+
+// Response header of the first successful request, sent with every later request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Capture {
+    pub header: String,          // Response header to read, e.g. X-Auth-Token
+    pub send_as: Option<String>, // Request header it is sent as, the same name when omitted
+}
+
+// One request of a chain, run in order by every virtual user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Step {
+    pub name: Option<String>,
+    pub url: String,
+    pub method: Option<HttpMethod>,
+    pub headers: Option<HashMap<String, String>>,
+    pub body: Option<String>,
+    #[serde(default)]
+    pub extract: HashMap<String, String>, // JSON path in the response -> variable for later steps
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LoadTestConfig {
+    #[serde(default)]
+    pub url: String, // Target endpoint, may be omitted when scenarios are set
+    pub url_list_file: Option<String>, // One URL per line, requests are spread over them in turn
+    pub har_file: Option<String>, // Recorded session replayed as a chain, replacing url/scenarios/chain
+    pub har_hosts: Option<Vec<String>>, // Only replay the HAR's requests to these hosts
+    pub method: Option<MethodMix>,
+    pub request_count: Option<usize>,
+    pub passes_over_data: Option<usize>, // Send every data row this many times instead of request_count
+    pub headers: Option<HashMap<String, String>>,
+    pub default_headers: Option<HashMap<String, String>>, // Added to every scenario's and step's headers
+    pub user_agent: Option<String>, // Sent unless the request's headers set a User-Agent
+    pub inject_request_id_header: Option<String>, // Header carrying a fresh UUID per request
+    pub auth: Option<Auth>,
+    pub query: Option<HashMap<String, String>>, // Query parameters, values may contain placeholders
+    pub body: Option<String>,                   // Static body or template for dynamic body
+    pub body_file: Option<String>,              // Read the body template from this file instead
+    pub upload_file: Option<String>,            // Stream this file as the body of every request
+    pub payload_dir: Option<String>, // Data values written as @path are read from this directory
+    pub body_type: Option<BodyType>, // How the body is encoded, raw when omitted
+    pub body_from_row: Option<bool>, // Send the data row as a JSON object when there is no body
+    pub data_file: Option<String>,   // Path to your Excel/CSV file
+    pub data_files: Option<Vec<DataFile>>, // One row of each file merged per request
+    pub data_has_headers: Option<bool>, // Whether the CSV's first row names the columns, true when omitted
+    pub data_sheet: Option<String>,     // Excel worksheet to read, the first one when omitted
+    pub stream_data: Option<bool>,      // Read CSV rows lazily instead of loading the whole file
+    pub data_order: Option<DataOrder>,  // How rows are picked, sequential when omitted
+    pub data_affinity: Option<DataAffinity>, // per_request when omitted, per_worker pins a row
+    pub timeout_ms: Option<u64>,        // Per-request timeout, unbounded when omitted
+    pub follow_redirects: Option<bool>, // Follow up to 10 redirects, true when omitted
+    pub insecure_tls: Option<bool>,     // Accept invalid TLS certificates, false when omitted
+    pub client_cert_path: Option<String>, // PEM certificate for mutual TLS
+    pub client_key_path: Option<String>, // PEM PKCS#8 private key for mutual TLS
+    pub proxy: Option<String>,          // Route all traffic through this proxy URL
+    pub resolve: Option<HashMap<String, String>>, // Host name to IP address, skipping DNS
+    pub cookie_jar: Option<bool>,       // Keep cookies between requests, false when omitted
+    pub compression: Option<bool>, // Accept and decompress gzip/deflate/brotli, true when omitted
+    pub compress_request: Option<bool>, // Gzip request bodies and send Content-Encoding: gzip
+    pub http_version: Option<HttpVersion>, // http1, http2 (prior knowledge) or auto when omitted
+    pub pool_max_idle_per_host: Option<usize>, // 0 opens a new connection for every request
+    pub pool_idle_timeout_secs: Option<u64>, // How long an idle connection is kept, 90 when omitted
+    pub concurrency: Option<usize>, // Max in-flight requests, unbounded when omitted
+    pub rate_limit_rps: Option<u32>, // Max requests started per second, unlimited when omitted
+    pub stages: Option<Vec<Stage>>, // Concurrency profile over time, replacing request_count/duration_secs
+    pub arrival_rate_rps: Option<u32>, // Start requests on a fixed schedule regardless of responses
+    pub traffic_profile: Option<Vec<RatePoint>>, // Arrival rate interpolated between points over time
+    pub duration_secs: Option<u64>, // Run for a fixed time instead of a fixed request count
+    pub max_duration_secs: Option<u64>, // Hard ceiling on the test's run time, in any mode
+    pub sla_p95_ms: Option<u64>,    // p95 target, shown green or red in the text report
+    pub latency_budget_ms: Option<u64>, // Target response time the Apdex score is computed against
+    pub expected_status: Option<u16>, // Only this status counts as success, any 2xx when omitted
+    pub success_statuses: Option<Vec<u16>>, // Statuses that count as success, any 2xx when omitted
+    pub assert_header: Option<HashMap<String, String>>, // Required response headers, any value when empty
+    pub capture: Option<Capture>, // Header copied from the first successful response to later requests
+    pub assert_body_contains: Option<String>, // Responses without this text count as failures
+    pub extract_metric: Option<String>, // JSON path of a number to aggregate, e.g. timing.server_ms
+    pub group_by: Option<String>, // Data column to break the latency statistics down by
+    pub max_body_bytes: Option<usize>, // Bodies read for assertions are cut off after this many bytes
+    pub sample_responses: Option<usize>, // Save this many responses to disk, failures first
+    pub sample_dir: Option<String>,    // Where samples are saved, samples when omitted
+    pub warmup_requests: Option<usize>, // Unmeasured requests sent before the test starts
+    pub think_time_ms: Option<ThinkTime>, // Pause after each request before the next one
+    pub jitter_ms: Option<u64>,        // Random delay up to this long before each request
+    pub ramp_up_secs: Option<u64>,     // Stagger request starts linearly over this period
+    pub retries: Option<usize>,        // Extra attempts on connection errors and 5xx responses
+    pub retry_backoff_ms: Option<u64>, // Initial delay between retries, doubled each attempt
+    pub abort_after_consecutive_failures: Option<usize>, // Stop the test after this many failures in a row
+    pub scenarios: Option<Vec<Scenario>>, // Weighted endpoints replacing url/method/headers/body
+    pub chain: Option<Vec<Step>>, // Requests sent in order, replacing url/method/headers/body
+    pub progress: Option<bool>,   // Show a progress bar on stderr, true when omitted
+    pub preflight: Option<bool>, // Check the target with one request before the test, true when omitted
+    pub seed: Option<u64>,       // Seed for data order, scenario picks, think time and generators
+}
+
+impl LoadTestConfig {
+    // Copy of this config that targets the scenario's endpoint instead
+    pub fn for_scenario(&self, scenario: &Scenario) -> LoadTestConfig {
+        LoadTestConfig {
+            url: scenario.url.clone(),
+            method: scenario.method.clone().map(MethodMix::Single),
+            headers: scenario.headers.clone(),
+            body: scenario.body.clone(),
+            scenarios: None,
+            ..self.clone()
+        }
+    }
+
+    // Whether requests get data rows, from data_file or data_files
+    pub fn has_data(&self) -> bool {
+        self.data_file.is_some() || self.data_files.is_some()
+    }
+
+    pub fn for_step(&self, step: &Step) -> LoadTestConfig {
+        LoadTestConfig {
+            url: step.url.clone(),
+            method: step.method.clone().map(MethodMix::Single),
+            headers: step.headers.clone(),
+            body: step.body.clone(),
+            chain: None,
+            ..self.clone()
+        }
+    }
 }
 
 pub fn load_config(file_path: &str) -> Result<LoadTestConfig, Box<dyn std::error::Error>> {
-    let file_contents = std::fs::read_to_string(file_path)?;
-    let config: LoadTestConfig = serde_yaml::from_str(&file_contents)?;
+    let file_contents = substitute_env_vars(&std::fs::read_to_string(file_path)?)?;
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+    // YAML unless the extension says otherwise
+    let mut config: LoadTestConfig = match extension.as_str() {
+        "toml" => toml::from_str(&file_contents)?,
+        "json" => serde_json::from_str(&file_contents)?,
+        _ => serde_yaml::from_str(&file_contents)?,
+    };
+
+    if let Some(ref body_file) = config.body_file {
+        let body = std::fs::read_to_string(body_file)
+            .map_err(|e| format!("failed to read body_file {}: {}", body_file, e))?;
+        config.body = Some(body);
+    }
+
     Ok(config)
 }
+
+// Address of a resolve entry, either `10.0.0.5` or `10.0.0.5:443`
+pub fn parse_resolve_address(address: &str) -> Option<SocketAddr> {
+    address
+        .parse()
+        .ok()
+        .or_else(|| address.parse().ok().map(|ip| SocketAddr::new(ip, 0)))
+}
+
+// validate_config's problems as one error, for the entry points that return errors
+pub(crate) fn ensure_valid(config: &LoadTestConfig) -> Result<(), Box<dyn std::error::Error>> {
+    validate_config(config).map_err(|errors| {
+        let list: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+        format!("invalid configuration:\n{}", list.join("\n")).into()
+    })
+}
+
+/// Checks a configuration for invalid values and combinations, returning every problem found
+pub fn validate_config(config: &LoadTestConfig) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    if config.url_list_file.is_some() && (config.scenarios.is_some() || config.chain.is_some()) {
+        errors.push("url_list_file cannot be combined with scenarios or chain".to_string());
+    }
+
+    if let Some(MethodMix::Weighted(ref methods)) = config.method {
+        if methods.is_empty() {
+            errors.push("method must contain at least one method".to_string());
+        } else if methods.iter().all(|m| m.weight == 0) {
+            errors.push("at least one method must have a weight greater than 0".to_string());
+        }
+        let conflicting = [
+            ("scenarios", config.scenarios.is_some()),
+            ("chain", config.chain.is_some()),
+            ("url_list_file", config.url_list_file.is_some()),
+            ("har_file", config.har_file.is_some()),
+        ];
+        for (name, is_set) in conflicting {
+            if is_set {
+                errors.push(format!(
+                    "a weighted method cannot be combined with {}",
+                    name
+                ));
+            }
+        }
+    }
+
+    match config.har_file {
+        Some(_) => {
+            let conflicting = [
+                ("url", !config.url.is_empty()),
+                ("url_list_file", config.url_list_file.is_some()),
+                ("scenarios", config.scenarios.is_some()),
+                ("chain", config.chain.is_some()),
+            ];
+            for (name, is_set) in conflicting {
+                if is_set {
+                    errors.push(format!("har_file and {} cannot both be set", name));
+                }
+            }
+        }
+        None if config.har_hosts.is_some() => {
+            errors.push("har_hosts requires har_file".to_string());
+        }
+        None => {}
+    }
+
+    match (&config.scenarios, &config.chain) {
+        (Some(_), Some(_)) => {
+            errors.push("scenarios and chain cannot both be set".to_string());
+        }
+        (None, Some(steps)) => {
+            if steps.is_empty() {
+                errors.push("chain must contain at least one step".to_string());
+            }
+            for step in steps {
+                check_url("chain step url", &step.url, &mut errors);
+            }
+        }
+        (Some(scenarios), None) => {
+            if scenarios.is_empty() {
+                errors.push("scenarios must contain at least one scenario".to_string());
+            } else if scenarios.iter().all(|s| s.weight == 0) {
+                errors.push("at least one scenario must have a weight greater than 0".to_string());
+            }
+            for scenario in scenarios {
+                check_url("scenario url", &scenario.url, &mut errors);
+                if scenario.rate_limit_rps == Some(0) {
+                    errors.push(format!(
+                        "rate_limit_rps of scenario {} must be greater than 0",
+                        scenario.display_name()
+                    ));
+                }
+            }
+        }
+        (None, None) => match (config.url.is_empty(), &config.url_list_file) {
+            (true, None) if config.har_file.is_some() => {}
+            (true, None) => {
+                errors
+                    .push("url must be set when no scenarios or chain are configured".to_string());
+            }
+            (false, Some(_)) => {
+                errors.push("url and url_list_file cannot both be set".to_string());
+            }
+            (false, None) => check_url("url", &config.url, &mut errors),
+            (true, Some(_)) => {}
+        },
+    }
+
+    match config.stages {
+        Some(ref stages) => {
+            if stages.is_empty() {
+                errors.push("stages must contain at least one stage".to_string());
+            }
+            if stages.iter().any(|s| s.duration_secs == 0) {
+                errors.push("every stage's duration_secs must be greater than 0".to_string());
+            }
+            let conflicting = [
+                ("request_count", config.request_count.is_some()),
+                ("duration_secs", config.duration_secs.is_some()),
+                ("concurrency", config.concurrency.is_some()),
+                ("ramp_up_secs", config.ramp_up_secs.is_some()),
+                ("arrival_rate_rps", config.arrival_rate_rps.is_some()),
+                ("traffic_profile", config.traffic_profile.is_some()),
+            ];
+            for (name, is_set) in conflicting {
+                if is_set {
+                    errors.push(format!("stages and {} cannot both be set", name));
+                }
+            }
+        }
+        None if config.traffic_profile.is_some() => {}
+        None => {
+            if config.request_count.is_none()
+                && config.duration_secs.is_none()
+                && config.passes_over_data.is_none()
+            {
+                errors.push(
+                    "either request_count, duration_secs, or passes_over_data must be set"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    if let Some(ref profile) = config.traffic_profile {
+        if profile.len() < 2 {
+            errors.push("traffic_profile must contain at least two points".to_string());
+        }
+        if profile.first().is_some_and(|p| p.offset_secs != 0) {
+            errors.push("traffic_profile must start at offset_secs 0".to_string());
+        }
+        if profile
+            .windows(2)
+            .any(|p| p[1].offset_secs <= p[0].offset_secs)
+        {
+            errors.push("traffic_profile offsets must be strictly increasing".to_string());
+        }
+        let conflicting = [
+            ("request_count", config.request_count.is_some()),
+            ("duration_secs", config.duration_secs.is_some()),
+            ("ramp_up_secs", config.ramp_up_secs.is_some()),
+            ("arrival_rate_rps", config.arrival_rate_rps.is_some()),
+            ("rate_limit_rps", config.rate_limit_rps.is_some()),
+        ];
+        for (name, is_set) in conflicting {
+            if is_set {
+                errors.push(format!("traffic_profile and {} cannot both be set", name));
+            }
+        }
+    }
+
+    // The request count follows from the data file, so nothing else may size the test
+    if config.passes_over_data.is_some() {
+        let conflicting = [
+            ("request_count", config.request_count.is_some()),
+            ("duration_secs", config.duration_secs.is_some()),
+            ("stages", config.stages.is_some()),
+            ("traffic_profile", config.traffic_profile.is_some()),
+        ];
+        for (name, is_set) in conflicting {
+            if is_set {
+                errors.push(format!("passes_over_data and {} cannot both be set", name));
+            }
+        }
+        if config.data_file.is_none() {
+            errors.push("passes_over_data requires a data_file".to_string());
+        }
+        if config.stream_data.unwrap_or(false) {
+            errors.push("passes_over_data cannot be used with stream_data".to_string());
+        }
+        // Random picks can repeat rows and miss others
+        if config.data_order.unwrap_or_default() == DataOrder::Random {
+            errors.push("passes_over_data cannot be used with the random data_order".to_string());
+        }
+    }
+
+    if config.group_by.is_some() && !config.has_data() {
+        errors.push("group_by requires a data_file or data_files".to_string());
+    }
+
+    let nonzero = [
+        ("request_count", config.request_count.map(|v| v as u64)),
+        (
+            "passes_over_data",
+            config.passes_over_data.map(|v| v as u64),
+        ),
+        ("duration_secs", config.duration_secs),
+        ("max_duration_secs", config.max_duration_secs),
+        ("latency_budget_ms", config.latency_budget_ms),
+        ("timeout_ms", config.timeout_ms),
+        ("rate_limit_rps", config.rate_limit_rps.map(u64::from)),
+        ("arrival_rate_rps", config.arrival_rate_rps.map(u64::from)),
+        ("concurrency", config.concurrency.map(|v| v as u64)),
+        ("max_body_bytes", config.max_body_bytes.map(|v| v as u64)),
+        (
+            "abort_after_consecutive_failures",
+            config.abort_after_consecutive_failures.map(|v| v as u64),
+        ),
+    ];
+    for (name, value) in nonzero {
+        if value == Some(0) {
+            errors.push(format!("{} must be greater than 0", name));
+        }
+    }
+
+    if let Some(ThinkTime::Range { min, max }) = config.think_time_ms {
+        if min > max {
+            errors.push("think_time_ms min must not be greater than max".to_string());
+        }
+    }
+
+    if config.client_cert_path.is_some() != config.client_key_path.is_some() {
+        errors.push("client_cert_path and client_key_path must be set together".to_string());
+    }
+
+    if config.arrival_rate_rps.is_some() && config.rate_limit_rps.is_some() {
+        errors.push("arrival_rate_rps and rate_limit_rps cannot both be set".to_string());
+    }
+
+    if config.success_statuses.is_some() && config.expected_status.is_some() {
+        errors.push("success_statuses and expected_status cannot both be set".to_string());
+    }
+
+    if config.success_statuses.as_ref().is_some_and(Vec::is_empty) {
+        errors.push("success_statuses must contain at least one status".to_string());
+    }
+
+    // Only bodies held in memory can be compressed, streamed ones are sent as they are read
+    if config.compress_request.unwrap_or(false) {
+        if config.upload_file.is_some() {
+            errors.push("compress_request cannot be used with upload_file".to_string());
+        }
+        if config.body_type == Some(BodyType::Multipart) {
+            errors.push("compress_request cannot be used with the multipart body_type".to_string());
+        }
+    }
+
+    if config.upload_file.is_some() && (config.body.is_some() || config.body_file.is_some()) {
+        errors.push("upload_file cannot be combined with body or body_file".to_string());
+    }
+
+    if config.body.is_some() && config.body_file.is_some() {
+        errors.push("body and body_file are mutually exclusive".to_string());
+    }
+
+    // A WebSocket test opens request_count connections at once, other executors don't apply
+    if is_websocket_url(&config.url) {
+        let conflicting = [
+            ("duration_secs", config.duration_secs.is_some()),
+            ("stages", config.stages.is_some()),
+            ("arrival_rate_rps", config.arrival_rate_rps.is_some()),
+            ("traffic_profile", config.traffic_profile.is_some()),
+            ("concurrency", config.concurrency.is_some()),
+            ("rate_limit_rps", config.rate_limit_rps.is_some()),
+            ("upload_file", config.upload_file.is_some()),
+            ("body_from_row", config.body_from_row.is_some()),
+            ("capture", config.capture.is_some()),
+            (
+                "inject_request_id_header",
+                config.inject_request_id_header.is_some(),
+            ),
+            (
+                "a weighted method",
+                matches!(config.method, Some(MethodMix::Weighted(_))),
+            ),
+        ];
+        for (name, is_set) in conflicting {
+            if is_set {
+                errors.push(format!("{} cannot be used with a WebSocket url", name));
+            }
+        }
+    }
+
+    if config.body_from_row.unwrap_or(false) {
+        if config.body.is_some() || config.body_file.is_some() || config.upload_file.is_some() {
+            errors.push(
+                "body_from_row cannot be combined with body, body_file, or upload_file".to_string(),
+            );
+        }
+        if !config.has_data() {
+            errors.push("body_from_row requires a data_file".to_string());
+        }
+    }
+
+    if config.stream_data.unwrap_or(false) {
+        let is_csv = config
+            .data_file
+            .as_deref()
+            .is_some_and(|f| f.to_lowercase().ends_with(".csv"));
+        if !is_csv {
+            errors.push("stream_data requires a CSV data_file".to_string());
+        }
+        if config.data_order.unwrap_or_default() != DataOrder::Sequential {
+            errors.push("stream_data only supports sequential data_order".to_string());
+        }
+    }
+
+    // Only the duration and staged tests keep workers around long enough to own a row
+    if config.data_affinity.unwrap_or_default() == DataAffinity::PerWorker {
+        let has_workers = config.stages.is_some()
+            || (config.duration_secs.is_some()
+                && config.arrival_rate_rps.is_none()
+                && config.traffic_profile.is_none());
+        if !has_workers {
+            errors.push("data_affinity per_worker requires duration_secs or stages".to_string());
+        }
+        if !config.has_data() {
+            errors.push("data_affinity per_worker requires a data_file".to_string());
+        }
+    }
+
+    if let Some(ref data_files) = config.data_files {
+        if config.data_file.is_some() {
+            errors.push("data_file and data_files are mutually exclusive".to_string());
+        }
+        if config.stream_data.unwrap_or(false) {
+            errors.push("stream_data cannot be used with data_files".to_string());
+        }
+        if data_files.is_empty() {
+            errors.push("data_files must list at least one file".to_string());
+        }
+        for file in data_files {
+            if !Path::new(file.path()).is_file() {
+                errors.push(format!("data_files entry {} does not exist", file.path()));
+            }
+        }
+    }
+
+    let files = [
+        ("data_file", &config.data_file),
+        ("body_file", &config.body_file),
+        ("upload_file", &config.upload_file),
+        ("url_list_file", &config.url_list_file),
+        ("har_file", &config.har_file),
+        ("client_cert_path", &config.client_cert_path),
+        ("client_key_path", &config.client_key_path),
+    ];
+    for (name, path) in files {
+        if let Some(path) = path {
+            if !Path::new(path).is_file() {
+                errors.push(format!("{} {} does not exist", name, path));
+            }
+        }
+    }
+
+    for (host, address) in config.resolve.iter().flatten() {
+        if parse_resolve_address(address).is_none() {
+            errors.push(format!(
+                "resolve address {} for {} must be an IP address, optionally with a port",
+                address, host
+            ));
+        }
+    }
+
+    for name in config.assert_header.iter().flat_map(HashMap::keys) {
+        if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            errors.push(format!("assert_header has an invalid header name {}", name));
+        }
+    }
+
+    if let Some(ref capture) = config.capture {
+        let names = std::iter::once(&capture.header).chain(capture.send_as.iter());
+        for name in names {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                errors.push(format!("capture has an invalid header name {}", name));
+            }
+        }
+    }
+
+    if let Some(ref name) = config.inject_request_id_header {
+        if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            errors.push(format!(
+                "inject_request_id_header has an invalid header name {}",
+                name
+            ));
+        }
+    }
+
+    if let Some(ref dir) = config.payload_dir {
+        if !Path::new(dir).is_dir() {
+            errors.push(format!("payload_dir {} is not a directory", dir));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// A URL must parse once its placeholders are filled in, so only the fixed parts are checked
+fn check_url(name: &str, url: &str, errors: &mut Vec<String>) {
+    let mut sample = String::new();
+    let mut rest = url;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        sample.push_str(&rest[..start]);
+        sample.push('x');
+        rest = &rest[start + end + 1..];
+    }
+    sample.push_str(rest);
+
+    if let Err(e) = reqwest::Url::parse(&sample) {
+        errors.push(format!("{} {} is not a valid URL: {}", name, url, e));
+    }
+}
+
+// Replaces every ${ENV:VAR_NAME} token with the value of that environment variable
+fn substitute_env_vars(contents: &str) -> Result<String, Box<dyn std::error::Error>> {
+    const PREFIX: &str = "${ENV:";
+
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find(PREFIX) {
+        let after_prefix = &rest[start + PREFIX.len()..];
+        let end = after_prefix
+            .find('}')
+            .ok_or("unterminated ${ENV:...} placeholder in config")?;
+        let name = &after_prefix[..end];
+        let value =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &after_prefix[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
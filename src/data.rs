@@ -1,10 +1,141 @@
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use calamine::{open_workbook_auto, Reader};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::{
+    config::{DataAffinity, DataFile, DataOrder, LoadTestConfig},
+    utils::{request_rng, RNG_DATA},
+};
+
+// Rows kept in flight between the CSV reader thread and the workers
+const STREAM_BUFFER: usize = 1024;
+
+// Column name to value. CSV and Excel cells are strings, JSON rows keep their values'
+// types and nesting.
+pub type Row = HashMap<String, serde_json::Value>;
+
+// Where requests get their data rows from
+pub enum DataSource {
+    Rows(Vec<Row>, DataOrder, u64),
+    Stream(Mutex<mpsc::Receiver<Row>>),
+    Fixed(Option<Row>),
+    Joined(Vec<(DataSource, Option<String>)>), // One source per data_files entry, with its prefix
+}
+
+impl DataSource {
+    pub fn open(config: &LoadTestConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let order = config.data_order.unwrap_or_default();
+        let seed = config.seed.unwrap_or_default();
+        if let Some(ref files) = config.data_files {
+            return open_joined(config, files);
+        }
+        let Some(ref file) = config.data_file else {
+            return Ok(DataSource::Rows(Vec::new(), order, seed));
+        };
+
+        let has_headers = config.data_has_headers.unwrap_or(true);
+        if config.stream_data.unwrap_or(false) {
+            return Ok(DataSource::Stream(Mutex::new(stream_csv_data(
+                file,
+                has_headers,
+            )?)));
+        }
+
+        let mut rows = load_data(file, has_headers, config.data_sheet.as_deref())?;
+        if order == DataOrder::Shuffle {
+            rows.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+        Ok(DataSource::Rows(rows, order, seed))
+    }
+
+    // Row for the request with the given index, None without a data file
+    pub async fn row(&self, index: usize) -> Option<Row> {
+        match self {
+            DataSource::Rows(rows, _, _) if rows.is_empty() => None,
+            DataSource::Rows(rows, order, seed) => {
+                // Shuffled rows are already in random order and cycle like sequential ones
+                let row_index = match order {
+                    DataOrder::Random => {
+                        request_rng(*seed, index, RNG_DATA).gen_range(0..rows.len())
+                    }
+                    DataOrder::Sequential | DataOrder::Shuffle => index % rows.len(),
+                };
+                rows.get(row_index).cloned()
+            }
+            DataSource::Stream(receiver) => receiver.lock().await.recv().await,
+            DataSource::Fixed(row) => row.clone(),
+            DataSource::Joined(sources) => {
+                let mut joined = HashMap::new();
+                for (source, prefix) in sources {
+                    // Boxed, a future can't contain itself
+                    let row = Box::pin(source.row(index)).await.unwrap_or_default();
+                    for (column, value) in row {
+                        let column = match prefix {
+                            Some(prefix) => format!("{}.{}", prefix, column),
+                            None => column,
+                        };
+                        joined.insert(column, value);
+                    }
+                }
+                Some(joined)
+            }
+        }
+    }
+
+    // Number of rows, None when they are streamed or fixed to one worker
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            DataSource::Rows(rows, _, _) => Some(rows.len()),
+            DataSource::Stream(_) | DataSource::Fixed(_) | DataSource::Joined(_) => None,
+        }
+    }
+
+    // Source a worker reads from for its lifetime. With per_worker affinity the worker takes
+    // the row for its own index once and sends it with every request.
+    pub async fn for_worker(
+        self: &Arc<Self>,
+        worker: usize,
+        affinity: DataAffinity,
+    ) -> Arc<DataSource> {
+        match affinity {
+            DataAffinity::PerRequest => Arc::clone(self),
+            DataAffinity::PerWorker => Arc::new(DataSource::Fixed(self.row(worker).await)),
+        }
+    }
+}
+
+// Each file is picked from independently, with its own order and random sequence, so e.g.
+// users can go in sequence while products are random
+fn open_joined(
+    config: &LoadTestConfig,
+    files: &[DataFile],
+) -> Result<DataSource, Box<dyn std::error::Error>> {
+    let has_headers = config.data_has_headers.unwrap_or(true);
+    let seed = config.seed.unwrap_or_default();
+
+    let mut sources = Vec::new();
+    for (position, file) in files.iter().enumerate() {
+        let order = file.order().or(config.data_order).unwrap_or_default();
+        let seed = seed.wrapping_add(position as u64);
+        let mut rows = load_data(file.path(), has_headers, config.data_sheet.as_deref())?;
+        if order == DataOrder::Shuffle {
+            rows.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+        sources.push((
+            DataSource::Rows(rows, order, seed),
+            file.prefix().map(str::to_string),
+        ));
+    }
+    Ok(DataSource::Joined(sources))
+}
 
 pub fn load_data(
     file_path: &str,
-) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error>> {
+    has_headers: bool,
+    sheet: Option<&str>,
+) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
     let path = Path::new(file_path);
     let extension = path
         .extension()
@@ -12,8 +143,9 @@ pub fn load_data(
         .unwrap_or("");
 
     match extension.to_lowercase().as_str() {
-        "csv" => load_csv_data(file_path),
-        "xls" | "xlsx" => load_excel_data(file_path),
+        "csv" => load_csv_data(file_path, has_headers),
+        "xls" | "xlsx" => load_excel_data(file_path, sheet),
+        "json" => load_json_data(file_path),
         _ => Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "Unsupported file format",
@@ -23,16 +155,17 @@ pub fn load_data(
 
 pub fn load_csv_data(
     file_path: &str,
-) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error>> {
-    let mut rdr = csv::Reader::from_path(file_path)?;
-    let headers = rdr.headers()?.clone();
+    has_headers: bool,
+) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+    let mut rdr = csv_reader(file_path, has_headers)?;
+    let headers = csv_headers(&mut rdr, has_headers)?;
 
     let mut data = Vec::new();
     for result in rdr.records() {
         let record = result?;
         let mut row_data = HashMap::new();
         for (header, field) in headers.iter().zip(record.iter()) {
-            row_data.insert(header.to_string(), field.to_string());
+            row_data.insert(header.clone(), field.into());
         }
         data.push(row_data);
     }
@@ -40,13 +173,46 @@ pub fn load_csv_data(
     Ok(data)
 }
 
+fn csv_reader(
+    file_path: &str,
+    has_headers: bool,
+) -> Result<csv::Reader<std::fs::File>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .has_headers(has_headers)
+        .from_path(file_path)
+}
+
+// Column names from the header row, or col0, col1, ... for headerless files
+fn csv_headers(
+    rdr: &mut csv::Reader<std::fs::File>,
+    has_headers: bool,
+) -> Result<Vec<String>, csv::Error> {
+    let headers = rdr.headers()?;
+    if has_headers {
+        Ok(headers.iter().map(str::to_string).collect())
+    } else {
+        Ok((0..headers.len()).map(|i| format!("col{}", i)).collect())
+    }
+}
+
 pub fn load_excel_data(
     file_path: &str,
-) -> Result<Vec<HashMap<String, String>>, Box<dyn std::error::Error>> {
+    sheet: Option<&str>,
+) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
     let mut workbook = open_workbook_auto(file_path)?;
-    let range = workbook
-        .worksheet_range_at(0)
-        .ok_or("Cannot find worksheet")??;
+    let range = match sheet {
+        Some(name) => workbook.worksheet_range(name).ok_or_else(|| {
+            format!(
+                "worksheet \"{}\" not found in {}, available sheets: {}",
+                name,
+                file_path,
+                workbook.sheet_names().join(", ")
+            )
+        })??,
+        None => workbook
+            .worksheet_range_at(0)
+            .ok_or("Cannot find worksheet")??,
+    };
 
     let headers: Vec<String> = range
         .rows()
@@ -62,7 +228,7 @@ pub fn load_excel_data(
         for (idx, cell) in row.iter().enumerate() {
             let value = cell.to_string(); // handle conversion as needed
             if idx < headers.len() {
-                row_data.insert(headers[idx].clone(), value);
+                row_data.insert(headers[idx].clone(), value.into());
             }
         }
         data.push(row_data);
@@ -70,3 +236,119 @@ pub fn load_excel_data(
 
     Ok(data)
 }
+
+pub fn load_json_data(file_path: &str) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+    let file_contents = std::fs::read_to_string(file_path)?;
+    let value: serde_json::Value = serde_json::from_str(&file_contents)?;
+    let rows = value
+        .as_array()
+        .ok_or("JSON data file must contain an array of objects")?;
+
+    let mut data = Vec::new();
+    for (idx, row) in rows.iter().enumerate() {
+        let object = row
+            .as_object()
+            .ok_or_else(|| format!("JSON data row {} is not an object", idx))?;
+        data.push(object.clone().into_iter().collect());
+    }
+
+    Ok(data)
+}
+
+// Reads one URL per line, skipping blank lines and lines starting with #
+pub fn load_url_list(file_path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let urls: Vec<String> = std::fs::read_to_string(file_path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+
+    if urls.is_empty() {
+        return Err(format!("URL list file {} contains no URLs", file_path).into());
+    }
+    Ok(urls)
+}
+
+// Reads a CSV file lazily on a background thread, starting over at the end of the file.
+// The thread stops once the receiver is dropped or a full pass yields no rows.
+pub fn stream_csv_data(
+    file_path: &str,
+    has_headers: bool,
+) -> Result<mpsc::Receiver<Row>, Box<dyn std::error::Error>> {
+    // Open once up front so a missing or unreadable file fails before the test starts
+    csv_reader(file_path, has_headers)?.headers()?;
+
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let file_path = file_path.to_string();
+
+    std::thread::spawn(move || loop {
+        let mut rdr = match csv_reader(&file_path, has_headers) {
+            Ok(rdr) => rdr,
+            Err(e) => {
+                log::error!("Failed to read data file {}: {}", file_path, e);
+                return;
+            }
+        };
+        let headers = match csv_headers(&mut rdr, has_headers) {
+            Ok(headers) => headers,
+            Err(e) => {
+                log::error!("Failed to read data file {}: {}", file_path, e);
+                return;
+            }
+        };
+
+        let mut sent_any = false;
+        for result in rdr.records() {
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    log::error!("Failed to read data file {}: {}", file_path, e);
+                    return;
+                }
+            };
+            let row_data = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, field)| (header.clone(), field.into()))
+                .collect();
+            if sender.blocking_send(row_data).is_err() {
+                return;
+            }
+            sent_any = true;
+        }
+
+        if !sent_any {
+            return;
+        }
+    });
+
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_rows_keep_their_values() {
+        let path = std::env::temp_dir().join(format!("loadfire-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"[{"user": {"name": "alice", "roles": ["admin"]}, "age": 30, "active": true}]"#,
+        )
+        .unwrap();
+        let rows = load_json_data(&path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0]["user"],
+            serde_json::json!({"name": "alice", "roles": ["admin"]})
+        );
+        assert_eq!(rows[0]["age"], 30);
+        assert_eq!(rows[0]["active"], true);
+        // Nested values are reached by path when substituted, not through extra columns
+        assert_eq!(rows[0].len(), 3);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::config::{HttpMethod, Step};
+
+// Headers left to the client instead of being replayed: HTTP/2 pseudo-headers aside, these
+// describe the recorded connection and body rather than the request. Conditional and cache
+// headers are left out too, or the server would answer from the browser's cache state with a
+// 304 instead of doing the work being measured.
+const SKIPPED_HEADERS: [&str; 12] = [
+    "host",
+    "content-length",
+    "connection",
+    "accept-encoding",
+    "transfer-encoding",
+    "if-none-match",
+    "if-modified-since",
+    "if-match",
+    "if-unmodified-since",
+    "if-range",
+    "cache-control",
+    "pragma",
+];
+
+// The parts of a HAR file a replay needs, everything else in it is ignored
+#[derive(Deserialize)]
+struct Har {
+    log: Log,
+}
+
+#[derive(Deserialize)]
+struct Log {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    request: Request,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<Header>,
+    post_data: Option<PostData>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct PostData {
+    text: Option<String>,
+}
+
+// Turns the requests of a HAR recording into chain steps, in the order they were recorded.
+// With hosts given, requests to other hosts, such as third-party assets, are left out.
+pub fn load_har(
+    file_path: &str,
+    hosts: Option<&[String]>,
+) -> Result<Vec<Step>, Box<dyn std::error::Error>> {
+    let har: Har = serde_json::from_str(&std::fs::read_to_string(file_path)?)
+        .map_err(|e| format!("failed to parse HAR file {}: {}", file_path, e))?;
+
+    let mut steps = Vec::new();
+    for request in har.log.entries.into_iter().map(|e| e.request) {
+        let Ok(url) = reqwest::Url::parse(&request.url) else {
+            log::warn!("Skipping HAR request with invalid URL {}", request.url);
+            continue;
+        };
+        // Recordings also hold data: and blob: URLs the browser never sent over the network
+        if !matches!(url.scheme(), "http" | "https") {
+            continue;
+        }
+        if let Some(hosts) = hosts {
+            let host = url.host_str().unwrap_or_default();
+            if !hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+                continue;
+            }
+        }
+        let Some(method) = parse_method(&request.method) else {
+            log::warn!(
+                "Skipping HAR request {} {}, the method isn't supported",
+                request.method,
+                request.url
+            );
+            continue;
+        };
+
+        let headers: HashMap<String, String> = request
+            .headers
+            .into_iter()
+            .filter(|h| {
+                !h.name.starts_with(':')
+                    && !SKIPPED_HEADERS.contains(&h.name.to_ascii_lowercase().as_str())
+            })
+            .map(|h| (h.name, h.value))
+            .collect();
+
+        steps.push(Step {
+            // Numbered, so requests to the same URL get their own section in the report
+            name: Some(format!(
+                "{}. {} {}",
+                steps.len() + 1,
+                request.method.to_ascii_uppercase(),
+                request.url
+            )),
+            url: request.url,
+            method: Some(method),
+            headers: Some(headers),
+            body: request.post_data.and_then(|p| p.text),
+            extract: HashMap::new(),
+        });
+    }
+
+    if steps.is_empty() {
+        return Err(format!("HAR file {} contains no requests to replay", file_path).into());
+    }
+    Ok(steps)
+}
+
+fn parse_method(method: &str) -> Option<HttpMethod> {
+    match method.to_ascii_uppercase().as_str() {
+        "GET" => Some(HttpMethod::Get),
+        "POST" => Some(HttpMethod::Post),
+        "PUT" => Some(HttpMethod::Put),
+        "PATCH" => Some(HttpMethod::Patch),
+        "DELETE" => Some(HttpMethod::Delete),
+        "HEAD" => Some(HttpMethod::Head),
+        "OPTIONS" => Some(HttpMethod::Options),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_and_cache_headers_are_not_replayed() {
+        let path = std::env::temp_dir().join(format!("loadfire-{}.har", uuid::Uuid::new_v4()));
+        let har = serde_json::json!({"log": {"entries": [{"request": {
+            "method": "GET",
+            "url": "http://example.com/",
+            "headers": [
+                {"name": ":authority", "value": "example.com"},
+                {"name": "Host", "value": "example.com"},
+                {"name": "If-None-Match", "value": "\"abc\""},
+                {"name": "If-Modified-Since", "value": "Tue, 01 Jan 2030 00:00:00 GMT"},
+                {"name": "Cache-Control", "value": "max-age=0"},
+                {"name": "Accept", "value": "text/html"},
+            ],
+        }}]}});
+        std::fs::write(&path, har.to_string()).unwrap();
+        let steps = load_har(&path.to_string_lossy(), None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let headers = steps[0].headers.as_ref().unwrap();
+        assert_eq!(
+            headers,
+            &HashMap::from([("Accept".to_string(), "text/html".to_string())])
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fmt::Write, time::Duration};
+
+use crate::{
+    report::{TestReport, TimeUnit},
+    utils::{format_duration, format_opt_duration},
+};
+
+const HISTOGRAM_BINS: usize = 20;
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 200.0;
+
+// Renders a self-contained HTML page with a summary table and latency histogram
+pub fn render_html_report(report: &TestReport, unit: TimeUnit) -> String {
+    let mut rows = vec![
+        ("Total Requests", report.total_requests.to_string()),
+        (
+            "Successful Requests",
+            report.successful_requests.to_string(),
+        ),
+        ("Failed Requests", report.failed_requests.to_string()),
+        ("Timeout Errors", report.timeout_errors.to_string()),
+        ("Connection Errors", report.connection_errors.to_string()),
+        ("HTTP Error Responses", report.http_errors.to_string()),
+        (
+            "Header Assertion Failures",
+            report.header_assertion_failures.to_string(),
+        ),
+        (
+            "Body Assertion Failures",
+            report.body_assertion_failures.to_string(),
+        ),
+        ("Other Errors", report.other_errors.to_string()),
+        ("Retried Requests", report.retried_requests.to_string()),
+        ("Dropped Requests", report.dropped_requests.to_string()),
+        ("Oversize Responses", report.oversize_responses.to_string()),
+        (
+            "Success Percentage",
+            format!("{:.2}%", report.success_percentage),
+        ),
+        (
+            "Failure Percentage",
+            format!("{:.2}%", report.failure_percentage),
+        ),
+        (
+            "Average Response Time",
+            format_duration(report.average_response_time, unit),
+        ),
+        (
+            "Response Time Std Dev",
+            format_duration(report.std_dev_response_time, unit),
+        ),
+        (
+            "Minimum Response Time",
+            format_duration(report.min_response_time, unit),
+        ),
+        (
+            "Maximum Response Time",
+            format_duration(report.max_response_time, unit),
+        ),
+        (
+            "P50 Response Time",
+            format_opt_duration(report.p50_response_time, unit),
+        ),
+        (
+            "P90 Response Time",
+            format_opt_duration(report.p90_response_time, unit),
+        ),
+        (
+            "P95 Response Time",
+            format_opt_duration(report.p95_response_time, unit),
+        ),
+        (
+            "P99 Response Time",
+            format_opt_duration(report.p99_response_time, unit),
+        ),
+        (
+            "Average Time to First Byte",
+            format_duration(report.average_time_to_first_byte, unit),
+        ),
+        (
+            "P50 Time to First Byte",
+            format_opt_duration(report.p50_time_to_first_byte, unit),
+        ),
+        (
+            "P95 Time to First Byte",
+            format_opt_duration(report.p95_time_to_first_byte, unit),
+        ),
+        (
+            "P99 Time to First Byte",
+            format_opt_duration(report.p99_time_to_first_byte, unit),
+        ),
+        ("Throughput", format!("{:.2} req/s", report.throughput)),
+        (
+            "Total Response Size",
+            format!("{} bytes", report.total_response_bytes),
+        ),
+        (
+            "Response Size (min / max / avg)",
+            format!(
+                "{} / {} / {:.0} bytes",
+                report.min_response_bytes, report.max_response_bytes, report.average_response_bytes
+            ),
+        ),
+        ("Transfer Rate", format!("{:.2} MB/s", report.transfer_rate)),
+        ("Seed", report.seed.to_string()),
+        (
+            "HTTP Version",
+            report
+                .http_version
+                .clone()
+                .unwrap_or_else(|| "N/A".to_string()),
+        ),
+        (
+            "Connection Pool",
+            format!(
+                "{} idle per host, {}s idle timeout",
+                report
+                    .pool_max_idle_per_host
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string()),
+                report.pool_idle_timeout_secs
+            ),
+        ),
+    ];
+    for (status, count) in &report.status_counts {
+        rows.push(("Status", format!("{}: {}", status, count)));
+    }
+    for class in report.status_classes.iter().filter(|c| c.count > 0) {
+        rows.push((
+            "Status Class",
+            format!(
+                "{}: {} ({:.2} %)",
+                class.class, class.count, class.percentage
+            ),
+        ));
+    }
+    if let Some(ref metric) = report.metric {
+        rows.push((
+            "Metric (min / avg / max / p95)",
+            format!(
+                "{}: {} / {:.2} / {} / {}",
+                metric.path, metric.min, metric.average, metric.max, metric.p95
+            ),
+        ));
+    }
+    if let Some(ratio) = report.request_compression_ratio {
+        rows.push(("Request Compression Ratio", format!("{:.2}x", ratio)));
+    }
+    if let Some(ref apdex) = report.apdex {
+        rows.push((
+            "Apdex",
+            format!(
+                "{:.2} with a {} ms budget ({} satisfied, {} tolerating, {} frustrated)",
+                apdex.score, apdex.budget_ms, apdex.satisfied, apdex.tolerating, apdex.frustrated
+            ),
+        ));
+    }
+    for bucket in &report.traffic_profile {
+        rows.push((
+            "Traffic Profile",
+            format!(
+                "{}-{}s: target {:.2} req/s, achieved {:.2} req/s",
+                bucket.start_secs, bucket.end_secs, bucket.target_rps, bucket.achieved_rps
+            ),
+        ));
+    }
+    if report.max_duration_reached {
+        rows.push((
+            "Max Duration",
+            "Reached, in-flight requests dropped".to_string(),
+        ));
+    }
+    if let Some(failures) = report.circuit_breaker_tripped_after {
+        rows.push((
+            "Circuit Breaker",
+            format!("Tripped after {} consecutive failures", failures),
+        ));
+    }
+    for error in &report.top_errors {
+        rows.push(("Error", format!("{}x {}", error.count, error.message)));
+    }
+
+    let mut table = String::new();
+    for (name, value) in rows {
+        let _ = writeln!(
+            table,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(name),
+            escape(&value)
+        );
+    }
+
+    let times: Vec<Duration> = report.results.iter().map(|r| r.elapsed).collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>LoadFire Report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 12px; text-align: left; }}
+td {{ text-align: right; }}
+</style>
+</head>
+<body>
+<h1>LoadFire Report</h1>
+{}<table>
+{}</table>
+<h2>Latency Histogram</h2>
+{}
+</body>
+</html>
+"#,
+        if report.interrupted {
+            "<p>Test interrupted, showing results for completed requests only.</p>\n"
+        } else {
+            ""
+        },
+        table,
+        render_histogram(&times, unit)
+    )
+}
+
+// Inline SVG bar chart of response times split into equal-width bins, labeled in the unit
+fn render_histogram(times: &[Duration], unit: TimeUnit) -> String {
+    let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) else {
+        return "<p>No requests were completed.</p>".to_string();
+    };
+
+    let (low, high) = (unit.convert(*min), unit.convert(*max));
+    let bin_width = ((high - low) / HISTOGRAM_BINS as f64).max(f64::EPSILON);
+
+    let mut bins = [0usize; HISTOGRAM_BINS];
+    for time in times {
+        let bin = (((unit.convert(*time) - low) / bin_width) as usize).min(HISTOGRAM_BINS - 1);
+        bins[bin] += 1;
+    }
+    let tallest = *bins.iter().max().unwrap_or(&1) as f64;
+
+    let bar_width = CHART_WIDTH / HISTOGRAM_BINS as f64;
+    let mut bars = String::new();
+    for (i, count) in bins.iter().enumerate() {
+        let height = *count as f64 / tallest * CHART_HEIGHT;
+        let _ = writeln!(
+            bars,
+            r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="#e4572e"><title>{:.*}-{:.*} {}: {}</title></rect>"##,
+            i as f64 * bar_width,
+            CHART_HEIGHT - height,
+            bar_width - 1.0,
+            height,
+            unit.precision(),
+            low + i as f64 * bin_width,
+            unit.precision(),
+            low + (i + 1) as f64 * bin_width,
+            unit.suffix(),
+            count
+        );
+    }
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">
+{bars}<line x1="0" y1="{ch}" x2="{cw}" y2="{ch}" stroke="#222"/>
+<text x="0" y="{ty}" font-size="12">{min}</text>
+<text x="{cw}" y="{ty}" font-size="12" text-anchor="end">{max}</text>
+</svg>"##,
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT + 20.0,
+        ch = CHART_HEIGHT,
+        cw = CHART_WIDTH,
+        ty = CHART_HEIGHT + 15.0,
+        bars = bars,
+        min = format_duration(*min, unit),
+        max = format_duration(*max, unit),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
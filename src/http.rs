@@ -1,175 +1,2419 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{IsTerminal, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
+use flate2::{write::GzEncoder, Compression};
 use futures::future::join_all;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use tokio::sync::Mutex;
+use indicatif::ProgressBar;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+        SET_COOKIE,
+    },
+    redirect::Policy,
+};
+use tokio::sync::{mpsc, Mutex, OnceCell, Semaphore};
 
 use crate::{
-    config::LoadTestConfig,
-    data::load_data,
-    utils::{display_progress, http_method_to_reqwest_method, replace_placeholders},
+    cancel::CancelToken,
+    config::{
+        ensure_valid, parse_resolve_address, Auth, BodyType, Capture, DataAffinity, HttpVersion,
+        LoadTestConfig, MethodMix, RatePoint, Stage, Step,
+    },
+    data::{load_url_list, DataSource, Row},
+    har::load_har,
+    limiter::RateLimiter,
+    report::{ErrorCount, MetricReport, RateBucket, RequestResult, ScenarioReport, TestReport},
+    utils::{
+        apdex, extract_json_path, http_method_to_reqwest_method, percentile, progress_bar,
+        replace_placeholders, request_rng, row_value, sparkline, status_classes, std_dev, truncate,
+        value_text, RNG_GENERATORS, RNG_JITTER, RNG_SCENARIO, RNG_THINK_TIME,
+    },
+    websocket::{self, is_websocket_url},
 };
 
+// reqwest's default for how long an idle pooled connection is kept
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+
+// Builds the client shared by every request of a load test
+pub fn build_client(
+    config: &LoadTestConfig,
+) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut client_builder = reqwest::Client::builder();
+
+    if config.insecure_tls.unwrap_or(false) {
+        log::warn!("TLS certificate verification is disabled (insecure_tls: true). Never use this against production.");
+        client_builder = client_builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(timeout_ms) = config.timeout_ms {
+        client_builder = client_builder.timeout(Duration::from_millis(timeout_ms));
+    }
+
+    let redirect_policy = match config.follow_redirects {
+        Some(false) => Policy::none(),
+        _ => Policy::limited(10),
+    };
+    client_builder = client_builder.redirect(redirect_policy);
+
+    // Client certificate for mutual TLS
+    if let (Some(ref cert_path), Some(ref key_path)) =
+        (&config.client_cert_path, &config.client_key_path)
+    {
+        let cert = std::fs::read(cert_path)
+            .map_err(|e| format!("failed to read client certificate {}: {}", cert_path, e))?;
+        let key = std::fs::read(key_path)
+            .map_err(|e| format!("failed to read client key {}: {}", key_path, e))?;
+        let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+            .map_err(|e| format!("invalid client certificate or key: {}", e))?;
+        client_builder = client_builder.identity(identity);
+    }
+
+    // Pinned hosts keep their name for TLS and the Host header, the port comes from the URL
+    for (host, address) in config.resolve.iter().flatten() {
+        let address = parse_resolve_address(address)
+            .ok_or_else(|| format!("invalid resolve address {} for {}", address, host))?;
+        client_builder = client_builder.resolve(host, address);
+    }
+
+    // Credentials embedded in the proxy URL are used for proxy authentication
+    if let Some(ref proxy_url) = config.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .map_err(|e| format!("invalid proxy URL {}: {}", proxy_url, e))?;
+        client_builder = client_builder.proxy(proxy);
+    }
+
+    // One jar for the whole client, so cookies are shared by every virtual user
+    if config.cookie_jar.unwrap_or(false) {
+        client_builder = client_builder.cookie_store(true);
+    }
+
+    // Disabling decompression also stops sending Accept-Encoding
+    let compression = config.compression.unwrap_or(true);
+    client_builder = client_builder
+        .gzip(compression)
+        .deflate(compression)
+        .brotli(compression);
+
+    // HTTP/2 without TLS needs prior knowledge since there is no ALPN to negotiate it
+    client_builder = match config.http_version.unwrap_or_default() {
+        HttpVersion::Http1 => client_builder.http1_only(),
+        HttpVersion::Http2 => client_builder.http2_prior_knowledge(),
+        HttpVersion::Auto => client_builder,
+    };
+
+    if let Some(ref user_agent) = config.user_agent {
+        client_builder = client_builder.user_agent(user_agent);
+    }
+
+    // Fewer idle connections means more handshakes, closer to many distinct clients
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        client_builder = client_builder.pool_max_idle_per_host(max_idle);
+    }
+    client_builder = client_builder.pool_idle_timeout(Duration::from_secs(
+        config
+            .pool_idle_timeout_secs
+            .unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+    ));
+
+    Ok(client_builder.build()?)
+}
+
 pub async fn send_request(
     config: &LoadTestConfig,
-    data_row: &Option<HashMap<String, String>>,
+    client: &reqwest::Client,
+    index: usize,
+    data_row: &Option<Row>,
+    extra_headers: &[(String, String)],
+    body_sizes: Option<&Mutex<(u64, u64)>>,
 ) -> Result<reqwest::Response, Box<dyn std::error::Error + Send>> {
-    let client = match reqwest::Client::builder()
-        .danger_accept_invalid_certs(true) // Only if you're sure about the security implications
+    let payloads = read_payloads(config.payload_dir.as_deref(), data_row).await?;
+    let mut request = build_request(config, client, index, data_row, &payloads)?;
+    for (name, value) in extra_headers {
+        request = request.header(name, value);
+    }
+    let mut request = request
         .build()
-    {
-        Ok(c) => c,
-        Err(e) => return Err(Box::new(e) as Box<dyn std::error::Error + Send>),
-    };
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    if config.compress_request.unwrap_or(false) {
+        if let (Some((original, compressed)), Some(sizes)) = (gzip_body(&mut request)?, body_sizes)
+        {
+            let mut sizes = sizes.lock().await;
+            sizes.0 += original;
+            sizes.1 += compressed;
+        }
+    }
+    let response = client
+        .execute(request)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+
+    Ok(response)
+}
 
+// Prepares a request with all placeholders substituted, ready to be sent. `payloads` holds
+// the files of the row's @path values, from read_payloads.
+pub fn build_request(
+    config: &LoadTestConfig,
+    client: &reqwest::Client,
+    index: usize,
+    data_row: &Option<Row>,
+    payloads: &HashMap<String, Vec<u8>>,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error + Send>> {
+    // A weighted mix is split into one target per method before requests are built
     let method = match &config.method {
-        Some(m) => http_method_to_reqwest_method(m),
-        None => reqwest::Method::GET,
+        Some(MethodMix::Single(m)) => http_method_to_reqwest_method(m),
+        Some(MethodMix::Weighted(_)) | None => reqwest::Method::GET,
     };
 
-    let mut request_builder = client.request(method, &config.url); // Example with GET, adjust as needed
+    // Generators draw from one sequence per request, so retries send the same values
+    let rng = RefCell::new(request_rng(
+        config.seed.unwrap_or_default(),
+        index,
+        RNG_GENERATORS,
+    ));
+    let substitute = |value: &str| match data_row {
+        Some(row) => replace_placeholders(value, row, &mut rng.borrow_mut()),
+        None => value.to_string(),
+    };
 
-    // Add headers if provided
-    if let Some(ref headers) = config.headers {
-        let mut header_map = HeaderMap::new();
-        for (key, value) in headers {
-            let header_name = match HeaderName::from_bytes(key.as_bytes()) {
-                Ok(h) => h,
-                Err(e) => return Err(Box::new(e) as Box<dyn std::error::Error + Send>),
-            };
-            let header_value = match HeaderValue::from_str(value) {
-                Ok(h) => h,
-                Err(e) => return Err(Box::new(e) as Box<dyn std::error::Error + Send>),
-            };
-            header_map.insert(header_name, header_value);
-        }
+    let url = substitute(&config.url);
+
+    let mut request_builder = client.request(method, &url);
+
+    // Default headers come first, so the request's own headers replace them
+    let headers = config
+        .default_headers
+        .iter()
+        .chain(&config.headers)
+        .flatten();
+    let mut header_map = HeaderMap::new();
+    for (key, value) in headers {
+        let (key, value) = (substitute(key), substitute(value));
+        let header_name = match HeaderName::from_bytes(key.as_bytes()) {
+            Ok(h) => h,
+            Err(e) => return Err(Box::new(e) as Box<dyn std::error::Error + Send>),
+        };
+        let header_value = match HeaderValue::from_str(&value) {
+            Ok(h) => h,
+            Err(e) => return Err(Box::new(e) as Box<dyn std::error::Error + Send>),
+        };
+        header_map.insert(header_name, header_value);
+    }
+    request_builder = request_builder.headers(header_map);
+
+    // Add authentication if provided
+    if let Some(ref auth) = config.auth {
+        request_builder = match auth {
+            Auth::Basic { username, password } => {
+                request_builder.basic_auth(substitute(username), Some(substitute(password)))
+            }
+            Auth::Bearer { token } => request_builder.bearer_auth(substitute(token)),
+        };
+    }
 
-        request_builder = request_builder.headers(header_map);
+    // Add query parameters if provided, reqwest takes care of the encoding
+    if let Some(ref query) = config.query {
+        let params: Vec<(String, String)> = query
+            .iter()
+            .map(|(key, value)| (key.clone(), substitute(value)))
+            .collect();
+        request_builder = request_builder.query(&params);
     }
 
-    // Add body if provided
+    // Add body if provided, with @path data values replaced by the files' contents
     if let Some(body) = &config.body {
-        let body = if let Some(row) = data_row {
-            replace_placeholders(body, &row)
-        } else {
-            body.to_string()
+        let whole_body = body
+            .trim()
+            .strip_prefix("${")
+            .and_then(|b| b.strip_suffix('}'))
+            .and_then(|column| payloads.get(column));
+
+        request_builder = match whole_body {
+            // A raw body that is only the payload is sent as-is, so binary files survive
+            Some(bytes) if config.body_type.unwrap_or_default() == BodyType::Raw => {
+                request_builder.body(bytes.clone())
+            }
+            _ if payloads.is_empty() => apply_body(config, request_builder, body, &substitute)?,
+            _ => {
+                let mut row = data_row.clone().unwrap_or_default();
+                for (column, bytes) in payloads {
+                    let text = String::from_utf8(bytes.clone()).map_err(|_| {
+                        Box::new(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!(
+                                "payload of {} is not UTF-8 text, binary payloads must be the whole raw body",
+                                column
+                            ),
+                        )) as Box<dyn std::error::Error + Send>
+                    })?;
+                    row.insert(column.clone(), text.into());
+                }
+                let substitute_payloads =
+                    |value: &str| replace_placeholders(value, &row, &mut rng.borrow_mut());
+                apply_body(config, request_builder, body, &substitute_payloads)?
+            }
         };
-        request_builder = request_builder.body(body);
     }
 
-    let response = request_builder
-        .send()
-        .await
+    // Columns starting with _ hold metadata, such as __index, and are left out
+    if config.body.is_none() && config.body_from_row.unwrap_or(false) {
+        let fields: serde_json::Map<String, serde_json::Value> = data_row
+            .iter()
+            .flatten()
+            .filter(|(column, _)| !column.starts_with('_'))
+            .map(|(column, value)| (column.clone(), value.clone()))
+            .collect();
+        request_builder = request_builder.json(&fields);
+    }
+
+    if let Some(ref path) = config.upload_file {
+        request_builder = apply_upload(config, request_builder, path)?;
+    }
+
+    Ok(request_builder)
+}
+
+// Gzips a body held in memory and marks it with Content-Encoding. Returns the body's size
+// before and after, None when there is no body.
+fn gzip_body(
+    request: &mut reqwest::Request,
+) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error + Send>> {
+    let Some(original) = request.body().and_then(reqwest::Body::as_bytes) else {
+        return Ok(None);
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed = encoder
+        .write_all(original)
+        .and_then(|_| encoder.finish())
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    let sizes = (original.len() as u64, compressed.len() as u64);
 
-    Ok(response)
+    let headers = request.headers_mut();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    // A length set in the headers would describe the uncompressed body
+    headers.remove(CONTENT_LENGTH);
+    *request.body_mut() = Some(compressed.into());
+    Ok(Some(sizes))
 }
 
-pub async fn perform_load_test(config: &LoadTestConfig) -> Result<(), Box<dyn std::error::Error>> {
-    // Load data if file is specified
-    let data_rows = if let Some(ref file) = config.data_file {
-        load_data(file)?
+// Fills in a random seed when none is configured, so the run can be replayed from the report
+fn with_seed(config: &LoadTestConfig) -> LoadTestConfig {
+    LoadTestConfig {
+        seed: Some(config.seed.unwrap_or_else(rand::random)),
+        ..config.clone()
+    }
+}
+
+// Replaces a har_file with the chain of its recorded requests
+fn with_har(config: &LoadTestConfig) -> Result<LoadTestConfig, Box<dyn std::error::Error>> {
+    match config.har_file {
+        Some(ref path) => Ok(LoadTestConfig {
+            chain: Some(load_har(path, config.har_hosts.as_deref())?),
+            ..config.clone()
+        }),
+        None => Ok(config.clone()),
+    }
+}
+
+// Sets the request count to cover every data row passes_over_data times
+fn with_passes(config: &LoadTestConfig, data: &DataSource) -> LoadTestConfig {
+    match (config.passes_over_data, data.len()) {
+        (Some(passes), Some(rows)) => LoadTestConfig {
+            request_count: Some(rows * passes),
+            ..config.clone()
+        },
+        _ => config.clone(),
+    }
+}
+
+// The request a virtual user starts with, the first step for chains
+fn first_request(config: &LoadTestConfig) -> LoadTestConfig {
+    match config.chain {
+        Some(ref steps) => config.for_step(&steps[0]),
+        None => config.clone(),
+    }
+}
+
+// Adds the request index to the data row so templates can use ${__index}
+fn with_index(data_row: Option<Row>, index: usize) -> Option<Row> {
+    let mut row = data_row.unwrap_or_default();
+    row.insert("__index".to_string(), index.into());
+    Some(row)
+}
+
+// Renders the first request of a test without sending anything
+pub async fn render_sample_request(
+    config: &LoadTestConfig,
+) -> Result<String, Box<dyn std::error::Error>> {
+    ensure_valid(config)?;
+    let config = &with_har(&with_seed(config))?;
+    let data_row = with_index(DataSource::open(config)?.row(0).await, 0);
+
+    let client = build_client(config)?;
+    let targets = Targets::new(config)?;
+    let target = &first_request(&targets.configs[0]);
+
+    let payloads = read_payloads(target.payload_dir.as_deref(), &data_row)
+        .await
+        .map_err(|e| e.to_string())?;
+    let request = build_request(target, &client, 0, &data_row, &payloads)
+        .and_then(|b| {
+            b.build()
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut sample = format!("{} {}\n", request.method(), request.url());
+    for (name, value) in request.headers() {
+        sample.push_str(&format!(
+            "{}: {}\n",
+            name,
+            value.to_str().unwrap_or("<binary>")
+        ));
+    }
+    if let Some(body) = request.body() {
+        sample.push('\n');
+        match body.as_bytes() {
+            Some(bytes) => sample.push_str(&String::from_utf8_lossy(bytes)),
+            None => sample.push_str("<streamed body>"),
+        }
+        sample.push('\n');
+    }
+
+    Ok(sample)
+}
+
+// Encodes the body template according to the configured body type
+fn apply_body(
+    config: &LoadTestConfig,
+    request_builder: reqwest::RequestBuilder,
+    body: &str,
+    substitute: &dyn Fn(&str) -> String,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error + Send>> {
+    match config.body_type.unwrap_or_default() {
+        BodyType::Raw => Ok(request_builder.body(substitute(body))),
+        BodyType::Json => {
+            let json: serde_json::Value = serde_json::from_str(&substitute(body))
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+            Ok(request_builder.json(&json))
+        }
+        BodyType::Form => {
+            let fields: Vec<(String, String)> = body_fields(body)?
+                .into_iter()
+                .map(|(key, value)| (key, substitute(&value)))
+                .collect();
+            Ok(request_builder.form(&fields))
+        }
+        BodyType::Multipart => {
+            let form = body_fields(body)?
+                .into_iter()
+                .fold(reqwest::multipart::Form::new(), |form, (key, value)| {
+                    form.text(key, substitute(&value))
+                });
+            Ok(request_builder.multipart(form))
+        }
+    }
+}
+
+// Reads the file of every data value written as @path, like curl's @file, without blocking
+// the runtime. Paths are resolved inside payload_dir, so a row can't point at files
+// elsewhere. Without a payload_dir, @ values are plain text.
+async fn read_payloads(
+    dir: Option<&str>,
+    data_row: &Option<Row>,
+) -> Result<HashMap<String, Vec<u8>>, Box<dyn std::error::Error + Send>> {
+    let payload_error = |message: String| {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            message,
+        )) as Box<dyn std::error::Error + Send>
+    };
+
+    let mut payloads = HashMap::new();
+    let (Some(dir), Some(row)) = (dir, data_row) else {
+        return Ok(payloads);
+    };
+    let base = tokio::fs::canonicalize(dir)
+        .await
+        .map_err(|e| payload_error(format!("payload_dir {}: {}", dir, e)))?;
+
+    for (column, value) in row {
+        let Some(relative) = value.as_str().and_then(|v| v.strip_prefix('@')) else {
+            continue;
+        };
+        // Canonicalizing resolves .. and symlinks before the containment check
+        let path = tokio::fs::canonicalize(base.join(relative))
+            .await
+            .map_err(|e| payload_error(format!("payload file {}: {}", relative, e)))?;
+        if !path.starts_with(&base) {
+            return Err(payload_error(format!(
+                "payload file {} is outside payload_dir",
+                relative
+            )));
+        }
+        let bytes = tokio::fs::read(&path)
+            .await
+            .map_err(|e| payload_error(format!("payload file {}: {}", relative, e)))?;
+        payloads.insert(column.clone(), bytes);
+    }
+    Ok(payloads)
+}
+
+// Streams a file as the body so large uploads are never held in memory
+fn apply_upload(
+    config: &LoadTestConfig,
+    request_builder: reqwest::RequestBuilder,
+    path: &str,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error + Send>> {
+    let file =
+        std::fs::File::open(path).map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?;
+    let length = file
+        .metadata()
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)?
+        .len();
+
+    let has_content_type = config
+        .default_headers
+        .iter()
+        .chain(&config.headers)
+        .flat_map(HashMap::keys)
+        .any(|k| k.eq_ignore_ascii_case("content-type"));
+    let request_builder = if has_content_type {
+        request_builder
     } else {
-        Vec::new()
+        request_builder.header(CONTENT_TYPE, "application/octet-stream")
     };
 
-    let response_times = Arc::new(Mutex::new(Vec::new()));
-    let success_count = Arc::new(Mutex::new(0usize));
-    let error_count = Arc::new(Mutex::new(0usize));
-    let requests_sent = Arc::new(Mutex::new(0usize));
-    let responses_received = Arc::new(Mutex::new(0usize));
+    Ok(request_builder
+        .header(CONTENT_LENGTH, length)
+        .body(reqwest::Body::from(tokio::fs::File::from_std(file))))
+}
 
-    let tasks: Vec<_> = (0..config.request_count)
-        .map(|index| {
-            let config = config.clone();
-            let response_times = Arc::clone(&response_times);
-            let success_count = Arc::clone(&success_count);
-            let error_count = Arc::clone(&error_count);
-            let requests_sent = Arc::clone(&requests_sent);
-            let responses_received = Arc::clone(&responses_received);
-            let data_row = if data_rows.is_empty() {
-                None
+// Parses a form or multipart body template, a flat JSON object, into fields
+fn body_fields(body: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send>> {
+    let invalid = || {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "form and multipart bodies must be a flat JSON object",
+        )) as Box<dyn std::error::Error + Send>
+    };
+
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|_| invalid())?;
+    let object = value.as_object().ok_or_else(invalid)?;
+
+    object
+        .iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(s) => Ok((key.clone(), s.clone())),
+            serde_json::Value::Number(n) => Ok((key.clone(), n.to_string())),
+            serde_json::Value::Bool(b) => Ok((key.clone(), b.to_string())),
+            serde_json::Value::Null => Ok((key.clone(), String::new())),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+// Sends a request, retrying connection errors and 5xx responses with exponential backoff.
+// Returns the final attempt's response with its time to first byte, which leaves out the
+// earlier attempts and the backoff, and whether any retry happened.
+async fn send_with_retries(
+    config: &LoadTestConfig,
+    client: &reqwest::Client,
+    index: usize,
+    data_row: &Option<Row>,
+    extra_headers: &[(String, String)],
+    body_sizes: Option<&Mutex<(u64, u64)>>,
+) -> (
+    Result<(reqwest::Response, Duration), Box<dyn std::error::Error + Send>>,
+    bool,
+) {
+    let retries = config.retries.unwrap_or(0);
+    let mut backoff = Duration::from_millis(config.retry_backoff_ms.unwrap_or(0));
+    let mut attempt = 0;
+
+    loop {
+        let attempt_start = Instant::now();
+        let result = send_request(config, client, index, data_row, extra_headers, body_sizes).await;
+        let should_retry = match &result {
+            // A 5xx listed in success_statuses is an expected result, not a failure
+            Ok(response) => {
+                response.status().is_server_error()
+                    && !config
+                        .success_statuses
+                        .as_ref()
+                        .is_some_and(|statuses| statuses.contains(&response.status().as_u16()))
+            }
+            Err(err) => err
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(is_transient),
+        };
+
+        if !should_retry || attempt >= retries {
+            // reqwest returns the response as soon as its headers are in
+            let result = result.map(|response| (response, attempt_start.elapsed()));
+            return (result, attempt > 0);
+        }
+
+        attempt += 1;
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+// Connection failures that may go away on the next attempt. Timeouts aren't retried, they
+// would add load to a server that is already slow, nor are errors that would repeat, like an
+// invalid header value.
+fn is_transient(err: &reqwest::Error) -> bool {
+    !err.is_timeout() && (err.is_connect() || err.is_request())
+}
+
+// Seconds of request rates shown in the live sparkline
+const SPARKLINE_SECONDS: usize = 30;
+
+// How often idle workers of a staged test check whether they are needed
+const STAGE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Outstanding request cap for arrival-rate tests without a concurrency setting
+const MAX_OUTSTANDING_REQUESTS: usize = 10_000;
+
+// Where response samples are saved when sample_dir isn't set
+const DEFAULT_SAMPLE_DIR: &str = "samples";
+
+// How often a traffic profile executor catches up with the requests that became due
+const PROFILE_TICK: Duration = Duration::from_millis(10);
+
+// Distinct failure messages shown in the report, and the width they're cut to
+const TOP_ERRORS: usize = 5;
+const ERROR_MESSAGE_WIDTH: usize = 120;
+
+// A response kept to be saved to disk for debugging
+struct ResponseSample {
+    index: usize,
+    success: bool,
+    head: String,
+    body: String,
+}
+
+// Counters and limits shared by every task for the lifetime of a load test
+#[derive(Default)]
+struct LoadTestState {
+    response_times: Mutex<Vec<Duration>>,
+    response_sizes: Mutex<Vec<u64>>,
+    success_count: Mutex<usize>,
+    error_count: Mutex<usize>,
+    timeout_error_count: Mutex<usize>,
+    connection_error_count: Mutex<usize>,
+    http_error_count: Mutex<usize>,
+    header_assertion_failure_count: Mutex<usize>,
+    oversize_response_count: Mutex<usize>, // Bodies cut off at max_body_bytes
+    body_assertion_failure_count: Mutex<usize>,
+    other_error_count: Mutex<usize>,
+    retried_count: Mutex<usize>,
+    dropped_count: Mutex<usize>,
+    profile_starts: Mutex<Vec<usize>>, // Requests started between each pair of profile points
+    status_counts: Mutex<HashMap<u16, usize>>,
+    metric_values: Mutex<Vec<f64>>,
+    missing_metric_count: Mutex<usize>,
+    error_messages: Mutex<HashMap<String, usize>>, // Occurrences of each distinct failure reason
+    samples: Mutex<Vec<ResponseSample>>,           // Responses saved to sample_dir after the test
+    results: Mutex<Vec<RequestResult>>,
+    requests_sent: Mutex<usize>,
+    responses_received: Mutex<usize>,
+    progress: Option<ProgressBar>,
+    cancelled: AtomicBool,
+    consecutive_failures: Mutex<usize>,
+    circuit_breaker: Option<usize>, // Consecutive failures that stop the test
+    circuit_breaker_tripped: AtomicBool,
+    rate_limiter: Option<RateLimiter>,
+    stage_clock: Option<Instant>, // Start of a staged test, to tell which stage a request is in
+    scenario_rate_limiters: Vec<Option<RateLimiter>>, // By scenario position, names can repeat
+    result_sender: Option<mpsc::UnboundedSender<RequestResult>>,
+    http_version: Mutex<Option<reqwest::Version>>,
+    captured_header: OnceCell<(String, String)>, // Name and value sent when capture is configured
+    request_body_sizes: Mutex<(u64, u64)>,       // Body bytes before and after compress_request
+}
+
+// Position of the scenario, chain step, listed URL or method a request was sent to, and its
+// name in the report. Names can repeat, e.g. two scenarios on the same URL, positions can't.
+type Target = (usize, String);
+
+async fn execute_request(
+    config: &LoadTestConfig,
+    client: &reqwest::Client,
+    index: usize,
+    scenario: Option<Target>,
+    data: &DataSource,
+    state: &LoadTestState,
+    semaphore: &Option<Arc<Semaphore>>,
+) {
+    // Wait for a free slot when concurrency is capped
+    let permit = match semaphore {
+        Some(s) => Some(s.acquire().await.expect("semaphore closed")),
+        None => None,
+    };
+
+    // Slots freed at the same moment would otherwise fire again in lockstep
+    if let Some(jitter_ms) = config.jitter_ms {
+        let mut rng = request_rng(config.seed.unwrap_or_default(), index, RNG_JITTER);
+        tokio::time::sleep(Duration::from_millis(rng.gen_range(0..=jitter_ms))).await;
+    }
+
+    // A scenario's own limit is waited for first, so a throttled scenario doesn't hold
+    // up the others by taking slots of the global limit while it waits
+    if let Some(limiter) = scenario
+        .as_ref()
+        .and_then(|(position, _)| state.scenario_rate_limiters.get(*position)?.as_ref())
+    {
+        limiter.acquire().await;
+    }
+    if let Some(ref limiter) = state.rate_limiter {
+        limiter.acquire().await;
+    }
+
+    if state.cancelled.load(Ordering::SeqCst) {
+        return;
+    }
+
+    // Fetched only once the request can run, so streamed rows aren't buffered by waiting tasks
+    let data_row = with_index(data.row(index).await, index);
+
+    match config.chain {
+        Some(ref steps) => run_chain(config, client, index, steps, data_row, state).await,
+        None => {
+            send_and_record(config, client, index, scenario, &data_row, state, false).await;
+        }
+    }
+
+    // Pause like a real user before this slot issues its next request
+    if let Some(ref think_time) = config.think_time_ms {
+        let mut rng = request_rng(config.seed.unwrap_or_default(), index, RNG_THINK_TIME);
+        tokio::time::sleep(think_time.sample(&mut rng)).await;
+    }
+    drop(permit);
+}
+
+// Runs the steps of a chain in order, adding the values they extract to the data row of
+// the following steps. A failed step or a value that can't be extracted ends the chain.
+async fn run_chain(
+    config: &LoadTestConfig,
+    client: &reqwest::Client,
+    index: usize,
+    steps: &[Step],
+    data_row: Option<Row>,
+    state: &LoadTestState,
+) {
+    let mut row = data_row.unwrap_or_default();
+
+    for (position, step) in steps.iter().enumerate() {
+        let step_config = config.for_step(step);
+        let name = step.name.clone().unwrap_or_else(|| step.url.clone());
+        let (succeeded, body) = send_and_record(
+            &step_config,
+            client,
+            index,
+            Some((position, name.clone())),
+            &Some(row.clone()),
+            state,
+            !step.extract.is_empty(),
+        )
+        .await;
+        // A recording's steps don't pass values to each other, so like the browser the replay
+        // goes on past a failed request
+        if !succeeded && config.har_file.is_none() {
+            return;
+        }
+        if step.extract.is_empty() {
+            continue;
+        }
+
+        let json: Option<serde_json::Value> = body.and_then(|b| serde_json::from_str(&b).ok());
+        for (path, variable) in &step.extract {
+            match json.as_ref().and_then(|j| extract_json_path(j, path)) {
+                Some(value) => {
+                    row.insert(variable.clone(), value.into());
+                }
+                None => {
+                    log::info!(
+                        "Request {} step {} has no value at {}, skipping the rest of the chain",
+                        index,
+                        name,
+                        path
+                    );
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Whether a response could still make it into the samples: while there is room, or when a
+// failure can take the place of a successful sample
+async fn wants_sample(config: &LoadTestConfig, state: &LoadTestState, status_ok: bool) -> bool {
+    let Some(limit) = config.sample_responses else {
+        return false;
+    };
+    let samples = state.samples.lock().await;
+    samples.len() < limit || (!status_ok && samples.iter().any(|s| s.success))
+}
+
+async fn keep_sample(config: &LoadTestConfig, state: &LoadTestState, sample: ResponseSample) {
+    let limit = config.sample_responses.unwrap_or_default();
+    let mut samples = state.samples.lock().await;
+    if samples.len() < limit {
+        samples.push(sample);
+    } else if !sample.success {
+        if let Some(slot) = samples.iter_mut().find(|s| s.success) {
+            *slot = sample;
+        }
+    }
+}
+
+// Status line and headers of a response, as they would appear on the wire
+fn response_head(response: &reqwest::Response) -> String {
+    let mut head = format!("{:?} {}\n", response.version(), response.status());
+    for (name, value) in response.headers() {
+        head.push_str(&format!(
+            "{}: {}\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    head
+}
+
+// Saves every sample as response-<index>-<ok|failed>.txt in the sample directory
+fn write_samples(dir: &str, samples: &[ResponseSample]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for sample in samples {
+        let outcome = if sample.success { "ok" } else { "failed" };
+        let path = Path::new(dir).join(format!("response-{}-{}.txt", sample.index, outcome));
+        std::fs::write(path, format!("{}\n{}", sample.head, sample.body))?;
+    }
+    Ok(())
+}
+
+// Reads the body up to `limit` bytes, so a huge response can't exhaust memory. Returns the
+// bytes read and whether the body was cut off.
+async fn read_capped_body(
+    response: &mut reqwest::Response,
+    limit: Option<usize>,
+) -> Result<(Vec<u8>, bool), reqwest::Error> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            body.extend_from_slice(&chunk[..limit - body.len()]);
+            return Ok((body, true));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((body, false))
+}
+
+// Size of a body that isn't needed, read chunk by chunk without keeping it
+async fn count_body_bytes(response: &mut reqwest::Response) -> Result<u64, reqwest::Error> {
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len() as u64;
+    }
+    Ok(size)
+}
+
+// Name of the first expected header that is missing or, when a value is given, differs
+fn header_mismatch(expected: &HashMap<String, String>, headers: &HeaderMap) -> Option<String> {
+    expected
+        .iter()
+        .find(|(name, value)| match headers.get(name.as_str()) {
+            Some(actual) => !value.is_empty() && actual.as_bytes() != value.as_bytes(),
+            None => true,
+        })
+        .map(|(name, _)| name.clone())
+}
+
+// Keeps the captured header of the first response that has it. A Set-Cookie header is sent
+// back without its attributes, as Cookie unless send_as names another header.
+fn capture_header(capture: &Capture, state: &LoadTestState, headers: &HeaderMap) {
+    if state.captured_header.initialized() {
+        return;
+    }
+    let Some(value) = headers
+        .get(capture.header.as_str())
+        .and_then(|v| v.to_str().ok())
+    else {
+        return;
+    };
+
+    let is_cookie = capture.header.eq_ignore_ascii_case(SET_COOKIE.as_str());
+    let value = if is_cookie {
+        value.split(';').next().unwrap_or_default().trim()
+    } else {
+        value
+    };
+    let name = match (&capture.send_as, is_cookie) {
+        (Some(name), _) => name.clone(),
+        (None, true) => "Cookie".to_string(),
+        (None, false) => capture.header.clone(),
+    };
+    // Another response may have been first, its value is kept
+    let _ = state.captured_header.set((name, value.to_string()));
+}
+
+async fn record_metric(state: &LoadTestState, path: &str, body: Option<&str>) {
+    let value = body
+        .and_then(|body| serde_json::from_str::<serde_json::Value>(body).ok())
+        .and_then(|json| extract_json_path(&json, path))
+        .and_then(|value| value.parse::<f64>().ok());
+    match value {
+        Some(value) => state.metric_values.lock().await.push(value),
+        None => *state.missing_metric_count.lock().await += 1,
+    }
+}
+
+// Counts a request that failed before its response was complete by the kind of error.
+// Returns the response time to report instead of the measured one: the timeout, for timeouts.
+async fn record_request_error(
+    config: &LoadTestConfig,
+    state: &LoadTestState,
+    message: &str,
+    reqwest_error: Option<&reqwest::Error>,
+) -> Option<Duration> {
+    record_error_message(state, message).await;
+    *state.error_count.lock().await += 1;
+
+    if reqwest_error.is_some_and(|e| e.is_timeout()) {
+        *state.timeout_error_count.lock().await += 1;
+        return config.timeout_ms.map(Duration::from_millis);
+    }
+    if reqwest_error.is_some_and(|e| e.is_connect()) {
+        *state.connection_error_count.lock().await += 1;
+    } else {
+        *state.other_error_count.lock().await += 1;
+    }
+    None
+}
+
+async fn record_error_message(state: &LoadTestState, message: &str) {
+    *state
+        .error_messages
+        .lock()
+        .await
+        .entry(truncate(message, ERROR_MESSAGE_WIDTH))
+        .or_insert(0) += 1;
+}
+
+// Sends one request and records its outcome. Returns whether it succeeded and, when
+// asked for, the body of a successful response.
+async fn send_and_record(
+    config: &LoadTestConfig,
+    client: &reqwest::Client,
+    index: usize,
+    scenario: Option<Target>,
+    data_row: &Option<Row>,
+    state: &LoadTestState,
+    read_body: bool,
+) -> (bool, Option<String>) {
+    {
+        let mut sent = state.requests_sent.lock().await;
+        *sent += 1;
+    }
+    update_progress(state).await;
+
+    let start_time = Instant::now();
+    // The stage the request starts in, a slow one that ends in the next stage or after the
+    // last one still belongs to it
+    let stage = state
+        .stage_clock
+        .zip(config.stages.as_deref())
+        .and_then(|(start, stages)| stage_at(stages, start.elapsed()))
+        .map(|(stage, _)| stage);
+    let mut elapsed = None;
+    let mut status_code = None;
+    let mut error_message = None;
+    let mut time_to_first_byte = None;
+    let mut succeeded = false;
+    let mut response_body = None;
+
+    // Headers that aren't part of the config: the captured one and a fresh request ID, kept
+    // for every retry so they all show up under the same ID in the server logs
+    let mut extra_headers: Vec<(String, String)> =
+        state.captured_header.get().cloned().into_iter().collect();
+    let request_id = config
+        .inject_request_id_header
+        .as_ref()
+        .map(|name| (name.clone(), uuid::Uuid::new_v4().to_string()));
+    extra_headers.extend(request_id.clone());
+    let (result, retried) = send_with_retries(
+        config,
+        client,
+        index,
+        data_row,
+        &extra_headers,
+        Some(&state.request_body_sizes),
+    )
+    .await;
+
+    if retried {
+        let mut retried_count = state.retried_count.lock().await;
+        *retried_count += 1;
+    }
+
+    match result {
+        Ok((mut response, first_byte)) => {
+            time_to_first_byte = Some(first_byte);
+            let status = response.status();
+            log::info!(
+                "Request {} to {} returned {} in {:?}",
+                index,
+                response.url(),
+                status,
+                start_time.elapsed()
+            );
+            status_code = Some(status.as_u16());
+            state
+                .http_version
+                .lock()
+                .await
+                .get_or_insert(response.version());
+            {
+                let mut status_counts = state.status_counts.lock().await;
+                *status_counts.entry(status.as_u16()).or_insert(0) += 1;
+            }
+
+            let status_ok = match (&config.success_statuses, config.expected_status) {
+                (Some(statuses), _) => statuses.contains(&status.as_u16()),
+                (None, Some(expected)) => status.as_u16() == expected,
+                (None, None) => status.is_success(),
+            };
+            if let Some(capture) = config.capture.as_ref().filter(|_| status_ok) {
+                capture_header(capture, state, response.headers());
+            }
+            let header_mismatch = match config.assert_header {
+                Some(ref expected) if status_ok => header_mismatch(expected, response.headers()),
+                _ => None,
+            };
+
+            // Only keep the body when an assertion or the caller needs it. Otherwise it is
+            // only counted, so the response time includes the whole transfer.
+            let needs_body = read_body
+                || config.assert_body_contains.is_some()
+                || config.extract_metric.is_some();
+            let sample_head = match wants_sample(config, state, status_ok).await {
+                true => Some(response_head(&response)),
+                false => None,
+            };
+            let response_size = if (status_ok && needs_body) || sample_head.is_some() {
+                read_capped_body(&mut response, config.max_body_bytes)
+                    .await
+                    .map(|(bytes, cut_off)| {
+                        response_body = Some(String::from_utf8_lossy(&bytes).into_owned());
+                        (bytes.len() as u64, cut_off)
+                    })
             } else {
-                data_rows.get(index % data_rows.len()).cloned()
+                count_body_bytes(&mut response)
+                    .await
+                    .map(|size| (size, false))
+            };
+            // A body that fails to arrive, e.g. a timeout halfway, fails the request like an
+            // error before the headers would
+            let body_error = match response_size {
+                Ok((size, cut_off)) => {
+                    if cut_off {
+                        *state.oversize_response_count.lock().await += 1;
+                    }
+                    state.response_sizes.lock().await.push(size);
+                    None
+                }
+                Err(err) => Some(err),
+            };
+            let body_ok = match config.assert_body_contains {
+                Some(ref expected) if status_ok => response_body
+                    .as_deref()
+                    .is_some_and(|body| body.contains(expected.as_str())),
+                _ => true,
             };
 
+            succeeded = body_error.is_none() && status_ok && header_mismatch.is_none() && body_ok;
+            if let Some(head) = sample_head {
+                let sample = ResponseSample {
+                    index,
+                    success: succeeded,
+                    head,
+                    body: response_body.clone().unwrap_or_default(),
+                };
+                keep_sample(config, state, sample).await;
+            }
+            if succeeded {
+                let mut success = state.success_count.lock().await;
+                *success += 1;
+                if let Some(ref path) = config.extract_metric {
+                    record_metric(state, path, response_body.as_deref()).await;
+                }
+            } else if let Some(err) = body_error {
+                log::info!("Request {} failed reading the body: {}", index, err);
+                error_message = Some(err.to_string());
+                elapsed = record_request_error(config, state, &err.to_string(), Some(&err)).await;
+            } else {
+                let mut errors = state.error_count.lock().await;
+                *errors += 1;
+                if !status_ok {
+                    *state.http_error_count.lock().await += 1;
+                    record_error_message(state, &status.to_string()).await;
+                } else if let Some(name) = header_mismatch {
+                    *state.header_assertion_failure_count.lock().await += 1;
+                    let message = format!("response header {} assertion failed", name);
+                    record_error_message(state, &message).await;
+                } else {
+                    *state.body_assertion_failure_count.lock().await += 1;
+                    record_error_message(state, "response body assertion failed").await;
+                }
+            }
+        }
+        Err(err) => {
+            log::info!(
+                "Request {} to {} failed after {:?}: {}",
+                index,
+                config.url,
+                start_time.elapsed(),
+                err
+            );
+            log::debug!("Request {} error detail: {:?}", index, err);
+            error_message = Some(err.to_string());
+            let reqwest_error = err.downcast_ref::<reqwest::Error>();
+            elapsed = record_request_error(config, state, &err.to_string(), reqwest_error).await;
+        }
+    }
+
+    let elapsed = elapsed.unwrap_or_else(|| start_time.elapsed());
+    let result = RequestResult {
+        index,
+        status: status_code,
+        elapsed,
+        time_to_first_byte,
+        error: error_message,
+        success: succeeded,
+        scenario_index: scenario.as_ref().map(|(position, _)| *position),
+        scenario: scenario.map(|(_, name)| name),
+        stage,
+        group: config
+            .group_by
+            .as_ref()
+            .and_then(|column| row_value(data_row.as_ref()?, column).map(value_text)),
+        request_id: request_id.map(|(_, id)| id),
+    };
+    record_result(state, result).await;
+
+    (succeeded, response_body)
+}
+
+// Adds a completed request to the results and moves the progress bar on
+async fn record_result(state: &LoadTestState, result: RequestResult) {
+    state.response_times.lock().await.push(result.elapsed);
+    if let Some(limit) = state.circuit_breaker {
+        let mut failures = state.consecutive_failures.lock().await;
+        *failures = if result.success { 0 } else { *failures + 1 };
+        if *failures >= limit && !state.circuit_breaker_tripped.swap(true, Ordering::SeqCst) {
+            log::warn!(
+                "Circuit breaker tripped after {} consecutive failures, stopping the test",
+                limit
+            );
+            state.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+    if let Some(ref sender) = state.result_sender {
+        // The receiver may already be gone, the result is still recorded below
+        let _ = sender.send(result.clone());
+    }
+    state.results.lock().await.push(result);
+
+    {
+        let mut received = state.responses_received.lock().await;
+        *received += 1;
+    }
+    if let Some(ref progress) = state.progress {
+        progress.inc(1);
+    }
+    update_progress(state).await;
+}
+
+// Shows the requests in flight and the errors so far next to the progress bar
+async fn update_progress(state: &LoadTestState) {
+    let Some(ref progress) = state.progress else {
+        return;
+    };
+    let in_flight = *state.requests_sent.lock().await - *state.responses_received.lock().await;
+    let errors = *state.error_count.lock().await;
+    progress.set_message(format!("{} in flight, {} errors", in_flight, errors));
+}
+
+// Shows the requests per second of the last seconds as a sparkline next to the progress
+// bar, only on a terminal so CI logs aren't flooded with redraws
+fn spawn_sparkline(state: &Arc<LoadTestState>) -> Option<tokio::task::JoinHandle<()>> {
+    let progress = state.progress.clone()?;
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+
+    let state = Arc::clone(state);
+    Some(tokio::spawn(async move {
+        let mut rates = VecDeque::with_capacity(SPARKLINE_SECONDS);
+        let mut last_received = 0;
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let received = *state.responses_received.lock().await;
+            if rates.len() == SPARKLINE_SECONDS {
+                rates.pop_front();
+            }
+            rates.push_back(received - last_received);
+            last_received = received;
+
+            let current = rates.back().copied().unwrap_or_default();
+            progress.set_prefix(format!(
+                "{} {} req/s",
+                sparkline(rates.make_contiguous()),
+                current
+            ));
+        }
+    }))
+}
+
+// Number of requests a test will send, None when it runs for a fixed time
+fn expected_requests(config: &LoadTestConfig) -> Option<usize> {
+    // Every iteration of a chain sends one request per step
+    let requests_per_iteration = config.chain.as_ref().map_or(1, Vec::len);
+    if let Some(ref profile) = config.traffic_profile {
+        return Some(profile_requests_due(profile, f64::MAX) as usize * requests_per_iteration);
+    }
+    match config.duration_secs {
+        Some(_) => None,
+        None if config.stages.is_some() => None,
+        None => config
+            .request_count
+            .map(|count| count * requests_per_iteration),
+    }
+}
+
+// Endpoints of a load test, one per scenario, one per listed URL, one per method of a weighted
+// mix or the top-level config alone
+struct Targets {
+    configs: Vec<LoadTestConfig>,
+    names: Vec<Option<String>>,
+    weights: Option<WeightedIndex<u32>>,
+    seed: u64,
+}
+
+impl Targets {
+    fn new(config: &LoadTestConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(ref path) = config.url_list_file {
+            let urls = load_url_list(path)?;
+            return Ok(Targets {
+                configs: urls
+                    .iter()
+                    .map(|url| LoadTestConfig {
+                        url: url.clone(),
+                        ..config.clone()
+                    })
+                    .collect(),
+                names: urls.into_iter().map(Some).collect(),
+                weights: None,
+                seed: config.seed.unwrap_or_default(),
+            });
+        }
+
+        if let Some(MethodMix::Weighted(ref methods)) = config.method {
+            return Ok(Targets {
+                configs: methods
+                    .iter()
+                    .map(|m| LoadTestConfig {
+                        method: Some(MethodMix::Single(m.method.clone())),
+                        ..config.clone()
+                    })
+                    .collect(),
+                names: methods
+                    .iter()
+                    .map(|m| Some(http_method_to_reqwest_method(&m.method).to_string()))
+                    .collect(),
+                weights: Some(WeightedIndex::new(methods.iter().map(|m| m.weight))?),
+                seed: config.seed.unwrap_or_default(),
+            });
+        }
+
+        match config.scenarios {
+            Some(ref scenarios) => Ok(Targets {
+                configs: scenarios.iter().map(|s| config.for_scenario(s)).collect(),
+                names: scenarios.iter().map(|s| Some(s.display_name())).collect(),
+                weights: Some(WeightedIndex::new(scenarios.iter().map(|s| s.weight))?),
+                seed: config.seed.unwrap_or_default(),
+            }),
+            None => Ok(Targets {
+                configs: vec![config.clone()],
+                names: vec![None],
+                weights: None,
+                seed: config.seed.unwrap_or_default(),
+            }),
+        }
+    }
+
+    // Picks the target of a request proportionally to its scenario or method weight, or takes
+    // the listed URLs in turn
+    fn pick(&self, request_index: usize) -> (&LoadTestConfig, Option<Target>) {
+        let index = match self.weights {
+            Some(ref weights) => {
+                weights.sample(&mut request_rng(self.seed, request_index, RNG_SCENARIO))
+            }
+            None => request_index % self.configs.len(),
+        };
+        let scenario = self.names[index].clone().map(|name| (index, name));
+        (&self.configs[index], scenario)
+    }
+}
+
+// WebSocket executor: opens request_count connections at once and keeps each open until
+// every connection has been attempted, so they are all held concurrently. A connection
+// counts as successful once its handshake, and its message exchange if any, is done.
+fn websocket_tasks(
+    config: &LoadTestConfig,
+    data: &Arc<DataSource>,
+    state: &Arc<LoadTestState>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let connection_count = config.request_count.unwrap_or_default();
+    let attempted = Arc::new(tokio::sync::Barrier::new(connection_count));
+
+    (0..connection_count)
+        .map(|index| {
+            let config = config.clone();
+            let data = Arc::clone(data);
+            let state = Arc::clone(state);
+            let attempted = Arc::clone(&attempted);
+
             tokio::spawn(async move {
-                {
-                    let mut sent = requests_sent.lock().await;
-                    *sent += 1;
+                tokio::time::sleep(ramp_up_delay(&config, index, connection_count)).await;
+                let connection = if state.cancelled.load(Ordering::SeqCst) {
+                    None
+                } else {
+                    Some(open_websocket(&config, index, &data, &state).await)
+                };
+                attempted.wait().await;
+                drop(connection);
+            })
+        })
+        .collect()
+}
 
-                    // Display progress at intervals or when all requests are sent
-                    if *sent % 1 == 0 || *sent == config.request_count {
-                        display_progress(*sent, 0);
-                    }
-                }
+async fn open_websocket(
+    config: &LoadTestConfig,
+    index: usize,
+    data: &DataSource,
+    state: &LoadTestState,
+) -> Option<websocket::Connection> {
+    let data_row = with_index(data.row(index).await, index);
+    let mut rng = request_rng(config.seed.unwrap_or_default(), index, RNG_GENERATORS);
+    let mut substitute = |value: &str| match data_row {
+        Some(ref row) => replace_placeholders(value, row, &mut rng),
+        None => value.to_string(),
+    };
+    let url = substitute(&config.url);
+    let headers: Vec<(String, String)> = config
+        .default_headers
+        .iter()
+        .chain(&config.headers)
+        .flatten()
+        .map(|(name, value)| (substitute(name), substitute(value)))
+        .collect();
+    let message = config.body.as_deref().map(&mut substitute);
 
-                let start_time = Instant::now();
-
-                match send_request(&config, &data_row).await {
-                    Ok(response) => {
-                        // println!("{:?}", response);
-                        if response.status().is_success() {
-                            let mut success = success_count.lock().await;
-                            *success += 1;
-                        } else {
-                            let mut errors = error_count.lock().await;
-                            *errors += 1;
-                        }
-                    }
-                    Err(_) => {
-                        // println!("{:?}", err);
-                        let mut errors = error_count.lock().await;
-                        *errors += 1;
+    *state.requests_sent.lock().await += 1;
+    update_progress(state).await;
+
+    let timeout = config.timeout_ms.map(Duration::from_millis);
+    let outcome = websocket::open(&url, &headers, message, timeout).await;
+
+    match outcome.failure {
+        None => *state.success_count.lock().await += 1,
+        Some(ref failure) => {
+            log::info!("Connection {} to {} failed: {}", index, url, failure);
+            *state.error_count.lock().await += 1;
+            let counter = match failure {
+                websocket::Failure::Timeout => &state.timeout_error_count,
+                websocket::Failure::Handshake(_) => &state.connection_error_count,
+                websocket::Failure::Reply(_) => &state.other_error_count,
+            };
+            *counter.lock().await += 1;
+            record_error_message(state, &failure.to_string()).await;
+        }
+    }
+    if let Some(status) = outcome.status {
+        *state.status_counts.lock().await.entry(status).or_insert(0) += 1;
+    }
+
+    let result = RequestResult {
+        index,
+        status: outcome.status,
+        elapsed: outcome.handshake,
+        time_to_first_byte: None,
+        error: outcome.failure.as_ref().map(ToString::to_string),
+        success: outcome.failure.is_none(),
+        scenario: None,
+        scenario_index: None,
+        stage: None,
+        group: None,
+        request_id: None,
+    };
+    record_result(state, result).await;
+
+    outcome.connection
+}
+
+// Spreads task start times linearly over the configured ramp-up period
+fn ramp_up_delay(config: &LoadTestConfig, index: usize, total: usize) -> Duration {
+    match config.ramp_up_secs {
+        Some(secs) if total > 0 => Duration::from_secs(secs).mul_f64(index as f64 / total as f64),
+        _ => Duration::ZERO,
+    }
+}
+
+// Staged executor: one worker per virtual user of the busiest stage, each sending requests
+// only while the current concurrency includes it
+fn staged_tasks(
+    stages: &[Stage],
+    affinity: DataAffinity,
+    client: &reqwest::Client,
+    targets: &Arc<Targets>,
+    data: &Arc<DataSource>,
+    state: &Arc<LoadTestState>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let workers = stages
+        .iter()
+        .map(|s| s.target_concurrency)
+        .max()
+        .unwrap_or_default();
+    let start = state.stage_clock.unwrap_or_else(Instant::now);
+    let next_index = Arc::new(Mutex::new(0usize));
+
+    (0..workers)
+        .map(|worker| {
+            let stages = stages.to_vec();
+            let client = client.clone();
+            let data = Arc::clone(data);
+            let state = Arc::clone(state);
+            let next_index = Arc::clone(&next_index);
+            let targets = Arc::clone(targets);
+
+            tokio::spawn(async move {
+                let data = data.for_worker(worker, affinity).await;
+                while !state.cancelled.load(Ordering::SeqCst) {
+                    let Some((_, concurrency)) = stage_at(&stages, start.elapsed()) else {
+                        break;
+                    };
+                    if worker >= concurrency {
+                        tokio::time::sleep(STAGE_POLL_INTERVAL).await;
+                        continue;
                     }
+
+                    let index = {
+                        let mut next = next_index.lock().await;
+                        *next += 1;
+                        *next - 1
+                    };
+                    let (target, scenario) = targets.pick(index);
+                    execute_request(target, &client, index, scenario, &data, &state, &None).await;
                 }
+            })
+        })
+        .collect()
+}
+
+// Stage index and concurrency at a point of a staged test. Concurrency moves linearly from
+// the previous stage's target, or 0 for the first stage, to the current one.
+fn stage_at(stages: &[Stage], elapsed: Duration) -> Option<(usize, usize)> {
+    let mut stage_start = Duration::ZERO;
+    let mut previous = 0;
+
+    for (index, stage) in stages.iter().enumerate() {
+        let length = Duration::from_secs(stage.duration_secs);
+        if elapsed < stage_start + length {
+            let progress = (elapsed - stage_start).as_secs_f64() / length.as_secs_f64();
+            let change = stage.target_concurrency as f64 - previous as f64;
+            return Some((
+                index,
+                (previous as f64 + change * progress).round() as usize,
+            ));
+        }
+        stage_start += length;
+        previous = stage.target_concurrency;
+    }
 
-                let elapsed = start_time.elapsed();
-                let mut times = response_times.lock().await;
-                times.push(elapsed);
+    None
+}
+
+// Sends one unmeasured request so an unreachable target fails the test right away instead
+// of after thousands of failed requests. Any response, whatever its status, counts as reachable.
+async fn preflight(
+    client: &reqwest::Client,
+    targets: &Targets,
+    data: &DataSource,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = first_request(targets.pick(0).0);
+    let data_row = with_index(data.row(0).await, 0);
+
+    let start = Instant::now();
+    match send_request(&target, client, 0, &data_row, &[], None).await {
+        Ok(_) => {
+            eprintln!("Preflight OK ({} ms)", start.elapsed().as_millis());
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "preflight request to {} failed, aborting the test: {}",
+            target.url, e
+        )
+        .into()),
+    }
+}
+
+// Open-model executor: starts a request on every tick of a fixed schedule, whether or not
+// earlier requests have returned. Ticks that find every slot taken are dropped and counted.
+// Scheduling stops early at `max_deadline`, the end of max_duration_secs.
+async fn arrival_rate_tasks(
+    config: &LoadTestConfig,
+    arrival_rate: u32,
+    client: &reqwest::Client,
+    targets: &Arc<Targets>,
+    data: &Arc<DataSource>,
+    state: &Arc<LoadTestState>,
+    max_deadline: Option<Instant>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    if config.ramp_up_secs.is_some() {
+        log::warn!("arrival_rate_rps is set, ramp_up_secs will be ignored");
+    }
+
+    let slots = Arc::new(Semaphore::new(
+        config.concurrency.unwrap_or(MAX_OUTSTANDING_REQUESTS),
+    ));
+    let deadline = config
+        .duration_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let request_count = config.request_count.unwrap_or_default();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / arrival_rate);
+    let mut tasks = Vec::new();
+    let mut index = 0;
+
+    loop {
+        interval.tick().await;
+        let done = match deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => index >= request_count,
+        };
+        if done || state.cancelled.load(Ordering::SeqCst) || past(max_deadline) {
+            break;
+        }
+
+        let Ok(permit) = Arc::clone(&slots).try_acquire_owned() else {
+            *state.dropped_count.lock().await += 1;
+            continue;
+        };
+
+        let (target, scenario) = targets.pick(index);
+        let config = target.clone();
+        let client = client.clone();
+        let data = Arc::clone(data);
+        let state = Arc::clone(state);
+
+        tasks.push(tokio::spawn(async move {
+            execute_request(&config, &client, index, scenario, &data, &state, &None).await;
+            drop(permit);
+        }));
+        index += 1;
+    }
+
+    tasks
+}
+
+// Open-model executor following a traffic profile: on every tick it starts the requests
+// that the interpolated rate has made due since the start. Like the arrival rate executor,
+// starts that find every slot taken are dropped and counted. Scheduling stops early at
+// `max_deadline`, the end of max_duration_secs.
+async fn traffic_profile_tasks(
+    config: &LoadTestConfig,
+    profile: &[RatePoint],
+    client: &reqwest::Client,
+    targets: &Arc<Targets>,
+    data: &Arc<DataSource>,
+    state: &Arc<LoadTestState>,
+    max_deadline: Option<Instant>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let slots = Arc::new(Semaphore::new(
+        config.concurrency.unwrap_or(MAX_OUTSTANDING_REQUESTS),
+    ));
+    let end = profile.last().map_or(0, |p| p.offset_secs) as f64;
+    let start = Instant::now();
+
+    let mut interval = tokio::time::interval(PROFILE_TICK);
+    let mut tasks = Vec::new();
+    let mut starts = vec![0; profile.len().saturating_sub(1)];
+    let mut issued = 0;
+    let mut index = 0;
+
+    loop {
+        interval.tick().await;
+        let elapsed = start.elapsed().as_secs_f64();
+        if elapsed >= end || state.cancelled.load(Ordering::SeqCst) || past(max_deadline) {
+            break;
+        }
+
+        let segment = profile
+            .windows(2)
+            .position(|p| elapsed < p[1].offset_secs as f64)
+            .unwrap_or_default();
+        let due = profile_requests_due(profile, elapsed) as usize;
+
+        while issued < due {
+            issued += 1;
+            let Ok(permit) = Arc::clone(&slots).try_acquire_owned() else {
+                *state.dropped_count.lock().await += 1;
+                continue;
+            };
+            starts[segment] += 1;
+
+            let (target, scenario) = targets.pick(index);
+            let config = target.clone();
+            let client = client.clone();
+            let data = Arc::clone(data);
+            let state = Arc::clone(state);
+
+            tasks.push(tokio::spawn(async move {
+                execute_request(&config, &client, index, scenario, &data, &state, &None).await;
+                drop(permit);
+            }));
+            index += 1;
+        }
+    }
+
+    *state.profile_starts.lock().await = starts;
+    tasks
+}
+
+fn past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Requests due after `elapsed` seconds of a traffic profile, the area under its rate curve
+fn profile_requests_due(profile: &[RatePoint], elapsed: f64) -> f64 {
+    let mut due = 0.0;
+    for pair in profile.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        let (start, end) = (from.offset_secs as f64, to.offset_secs as f64);
+        if elapsed <= start {
+            break;
+        }
+
+        let secs = elapsed.min(end) - start;
+        let (from_rps, to_rps) = (f64::from(from.rps), f64::from(to.rps));
+        let rate_reached = from_rps + (to_rps - from_rps) * secs / (end - start);
+        due += (from_rps + rate_reached) / 2.0 * secs;
+    }
+    due
+}
+
+// Target and achieved rate between every pair of traffic profile points
+fn traffic_profile_report(config: &LoadTestConfig, starts: &[usize]) -> Vec<RateBucket> {
+    let Some(ref profile) = config.traffic_profile else {
+        return Vec::new();
+    };
+
+    profile
+        .windows(2)
+        .zip(starts)
+        .map(|(pair, &started)| {
+            let secs = (pair[1].offset_secs - pair[0].offset_secs) as f64;
+            RateBucket {
+                start_secs: pair[0].offset_secs,
+                end_secs: pair[1].offset_secs,
+                target_rps: (f64::from(pair[0].rps) + f64::from(pair[1].rps)) / 2.0,
+                achieved_rps: started as f64 / secs,
+            }
+        })
+        .collect()
+}
+
+// Per-scenario statistics in the order the scenarios are configured
+fn scenario_reports(config: &LoadTestConfig, results: &[RequestResult]) -> Vec<ScenarioReport> {
+    // Chain steps are reported like scenarios, one section per step
+    let names: Vec<String> = match (&config.scenarios, &config.chain) {
+        (Some(scenarios), _) => scenarios.iter().map(|s| s.display_name()).collect(),
+        (None, Some(steps)) => steps
+            .iter()
+            .map(|s| s.name.clone().unwrap_or_else(|| s.url.clone()))
+            .collect(),
+        (None, None) => return Vec::new(),
+    };
+
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(position, name)| {
+            let scenario_results: Vec<&RequestResult> = results
+                .iter()
+                .filter(|r| r.scenario_index == Some(position))
+                .collect();
+            group_report(name, scenario_results)
+        })
+        .collect()
+}
+
+// Per-URL statistics of a url_list_file test, in file order
+fn url_reports(
+    config: &LoadTestConfig,
+    targets: &Targets,
+    results: &[RequestResult],
+) -> Vec<ScenarioReport> {
+    if config.url_list_file.is_none() {
+        return Vec::new();
+    }
+    target_reports(targets, results)
+}
 
-                {
-                    let mut received = responses_received.lock().await;
-                    *received += 1;
+// Per-method statistics of a weighted method mix, in the order the methods are configured
+fn method_reports(
+    config: &LoadTestConfig,
+    targets: &Targets,
+    results: &[RequestResult],
+) -> Vec<ScenarioReport> {
+    if !matches!(config.method, Some(MethodMix::Weighted(_))) {
+        return Vec::new();
+    }
+    target_reports(targets, results)
+}
+
+fn target_reports(targets: &Targets, results: &[RequestResult]) -> Vec<ScenarioReport> {
+    targets
+        .names
+        .iter()
+        .enumerate()
+        .filter_map(|(position, name)| {
+            let target_results = results
+                .iter()
+                .filter(|r| r.scenario_index == Some(position))
+                .collect();
+            Some(group_report(name.clone()?, target_results))
+        })
+        .collect()
+}
+
+// Statistics of every stage of a staged test, in order
+fn stage_reports(config: &LoadTestConfig, results: &[RequestResult]) -> Vec<ScenarioReport> {
+    let Some(ref stages) = config.stages else {
+        return Vec::new();
+    };
 
-                    if *received % 1 == 0 || *received == config.request_count {
-                        display_progress(config.request_count, *received);
+    stages
+        .iter()
+        .enumerate()
+        .map(|(index, stage)| {
+            let name = format!(
+                "{} ({}s to {} concurrent)",
+                index + 1,
+                stage.duration_secs,
+                stage.target_concurrency
+            );
+            let stage_results = results.iter().filter(|r| r.stage == Some(index)).collect();
+            group_report(name, stage_results)
+        })
+        .collect()
+}
+
+// Statistics for every value of the group_by column, the slowest p95 first so expensive
+// inputs stand out
+fn group_by_reports(config: &LoadTestConfig, results: &[RequestResult]) -> Vec<ScenarioReport> {
+    let Some(ref column) = config.group_by else {
+        return Vec::new();
+    };
+
+    let mut groups: BTreeMap<&str, Vec<&RequestResult>> = BTreeMap::new();
+    for result in results {
+        if let Some(ref value) = result.group {
+            groups.entry(value).or_default().push(result);
+        }
+    }
+
+    let mut reports: Vec<ScenarioReport> = groups
+        .into_iter()
+        .map(|(value, group_results)| group_report(format!("{}={}", column, value), group_results))
+        .collect();
+    reports.sort_by_key(|r| std::cmp::Reverse(r.p95_response_time));
+    reports
+}
+
+fn group_report(name: String, results: Vec<&RequestResult>) -> ScenarioReport {
+    let mut times: Vec<Duration> = results.iter().map(|r| r.elapsed).collect();
+    times.sort();
+    let total: Duration = times.iter().sum();
+    let successful = results.iter().filter(|r| r.success).count();
+
+    ScenarioReport {
+        total_requests: times.len(),
+        successful_requests: successful,
+        failed_requests: times.len() - successful,
+        average_response_time: total.checked_div(times.len() as u32).unwrap_or_default(),
+        p95_response_time: if times.is_empty() {
+            None
+        } else {
+            Some(percentile(&times, 95.0))
+        },
+        name,
+    }
+}
+
+pub async fn perform_load_test(
+    config: &LoadTestConfig,
+    result_sender: Option<mpsc::UnboundedSender<RequestResult>>,
+    cancel: CancelToken,
+) -> Result<TestReport, Box<dyn std::error::Error>> {
+    // Library callers may not have validated, and invalid values like a zero rate panic
+    ensure_valid(config)?;
+    let config = &with_har(&with_seed(config))?;
+
+    // Load data if file is specified
+    let data = Arc::new(DataSource::open(config)?);
+    let config = &with_passes(config, &data);
+
+    let client = build_client(config)?;
+    let targets = Arc::new(Targets::new(config)?);
+    let semaphore = config
+        .concurrency
+        .map(|limit| Arc::new(Semaphore::new(limit)));
+
+    let is_websocket = is_websocket_url(&config.url);
+    if is_websocket && config.warmup_requests.is_some() {
+        log::warn!("WebSocket tests don't send warmup requests, warmup_requests will be ignored");
+    }
+
+    if config.preflight.unwrap_or(true) && !is_websocket {
+        preflight(&client, &targets, &data).await?;
+    }
+
+    // Warm up connections and caches, nothing from this phase is recorded
+    if let Some(warmup_requests) = config.warmup_requests.filter(|_| !is_websocket) {
+        let warmup_tasks: Vec<_> = (0..warmup_requests)
+            .map(|index| {
+                let config = first_request(targets.pick(index).0);
+                let client = client.clone();
+                let semaphore = semaphore.clone();
+                let data = Arc::clone(&data);
+
+                tokio::spawn(async move {
+                    let _permit = match semaphore {
+                        Some(ref s) => Some(s.acquire().await.expect("semaphore closed")),
+                        None => None,
+                    };
+                    let data_row = with_index(data.row(index).await, index);
+                    let _ = send_request(&config, &client, index, &data_row, &[], None).await;
+                })
+            })
+            .collect();
+        join_all(warmup_tasks).await;
+    }
+
+    let state = Arc::new(LoadTestState {
+        rate_limiter: config.rate_limit_rps.map(RateLimiter::new),
+        stage_clock: config.stages.as_ref().map(|_| Instant::now()),
+        scenario_rate_limiters: config
+            .scenarios
+            .iter()
+            .flatten()
+            .map(|s| s.rate_limit_rps.map(RateLimiter::new))
+            .collect(),
+        result_sender,
+        circuit_breaker: config.abort_after_consecutive_failures,
+        progress: config
+            .progress
+            .unwrap_or(true)
+            .then(|| progress_bar(expected_requests(config))),
+        ..Default::default()
+    });
+
+    // Stop issuing new requests once cancelled and report what completed so far
+    let cancel_handler = {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            cancel.cancelled().await;
+            state.cancelled.store(true, Ordering::SeqCst);
+        })
+    };
+
+    let sparkline_updater = spawn_sparkline(&state);
+
+    // Wall-clock start of the whole test, used for throughput
+    let wall_start = Instant::now();
+    // Starts before the executors, since the open-model ones schedule before returning
+    let max_deadline = config
+        .max_duration_secs
+        .map(|secs| wall_start + Duration::from_secs(secs));
+
+    let tasks: Vec<_> = if is_websocket {
+        websocket_tasks(config, &data, &state)
+    } else if let Some(ref stages) = config.stages {
+        let affinity = config.data_affinity.unwrap_or_default();
+        staged_tasks(stages, affinity, &client, &targets, &data, &state)
+    } else if let Some(ref profile) = config.traffic_profile {
+        traffic_profile_tasks(
+            config,
+            profile,
+            &client,
+            &targets,
+            &data,
+            &state,
+            max_deadline,
+        )
+        .await
+    } else if let Some(arrival_rate) = config.arrival_rate_rps {
+        arrival_rate_tasks(
+            config,
+            arrival_rate,
+            &client,
+            &targets,
+            &data,
+            &state,
+            max_deadline,
+        )
+        .await
+    } else if let Some(duration_secs) = config.duration_secs {
+        if config.request_count.is_some() {
+            log::warn!("duration_secs is set, request_count will be ignored");
+        }
+
+        // Each worker keeps issuing requests until the test duration elapses
+        let test_duration = Duration::from_secs(duration_secs);
+        let test_start = Instant::now();
+        let next_index = Arc::new(Mutex::new(0usize));
+
+        let workers = config.concurrency.unwrap_or(1);
+
+        (0..workers)
+            .map(|worker| {
+                let config = config.clone();
+                let client = client.clone();
+                let data = Arc::clone(&data);
+                let state = Arc::clone(&state);
+                let next_index = Arc::clone(&next_index);
+                let targets = Arc::clone(&targets);
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(ramp_up_delay(&config, worker, workers)).await;
+                    let data = data
+                        .for_worker(worker, config.data_affinity.unwrap_or_default())
+                        .await;
+
+                    while test_start.elapsed() < test_duration
+                        && !state.cancelled.load(Ordering::SeqCst)
+                    {
+                        let index = {
+                            let mut next = next_index.lock().await;
+                            *next += 1;
+                            *next - 1
+                        };
+                        let (target, scenario) = targets.pick(index);
+
+                        execute_request(target, &client, index, scenario, &data, &state, &None)
+                            .await;
                     }
-                }
+                })
+            })
+            .collect()
+    } else {
+        let request_count = config.request_count.unwrap_or_default();
+
+        (0..request_count)
+            .map(|index| {
+                let (target, scenario) = targets.pick(index);
+                let config = target.clone();
+                let client = client.clone();
+                let state = Arc::clone(&state);
+                let semaphore = semaphore.clone();
+                let data = Arc::clone(&data);
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(ramp_up_delay(&config, index, request_count)).await;
+                    execute_request(&config, &client, index, scenario, &data, &state, &semaphore)
+                        .await;
+                })
             })
+            .collect()
+    };
+
+    // Wait for all tasks to complete, or stop them where they are at max_duration_secs
+    let abort_handles: Vec<_> = tasks.iter().map(|task| task.abort_handle()).collect();
+    let all_tasks = join_all(tasks);
+    let mut max_duration_reached = false;
+    match (config.max_duration_secs, max_deadline) {
+        (Some(secs), Some(deadline)) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let finished = tokio::time::timeout(remaining, all_tasks).await.is_ok();
+            // A schedule cut short at the deadline can leave nothing in flight to time out
+            if !finished || past(max_deadline) {
+                log::warn!("max_duration_secs of {}s reached, stopping the test", secs);
+                state.cancelled.store(true, Ordering::SeqCst);
+                abort_handles.iter().for_each(|handle| handle.abort());
+                max_duration_reached = true;
+            }
+        }
+        _ => {
+            all_tasks.await;
+        }
+    }
+    let wall_elapsed = wall_start.elapsed();
+    cancel_handler.abort();
+    if let Some(updater) = sparkline_updater {
+        updater.abort();
+    }
+    if let Some(ref progress) = state.progress {
+        progress.finish();
+        // The finished bar stays on screen, move past it before the report is printed
+        if !progress.is_hidden() {
+            eprintln!();
+        }
+    }
+
+    if config.sample_responses.is_some() {
+        let dir = config.sample_dir.as_deref().unwrap_or(DEFAULT_SAMPLE_DIR);
+        let mut samples = std::mem::take(&mut *state.samples.lock().await);
+        samples.sort_by_key(|s| s.index);
+        if let Err(e) = write_samples(dir, &samples) {
+            log::warn!("Failed to save response samples to {}: {}", dir, e);
+        }
+    }
+
+    Ok(build_report(config, &targets, &state, wall_elapsed, max_duration_reached).await)
+}
+
+// Aggregates the state of a finished test into its report. Every statistic has a defined
+// value when no request completed, e.g. after an early Ctrl-C.
+async fn build_report(
+    config: &LoadTestConfig,
+    targets: &Targets,
+    state: &LoadTestState,
+    wall_elapsed: Duration,
+    max_duration_reached: bool,
+) -> TestReport {
+    let completed = *state.responses_received.lock().await;
+    let total_duration: Duration = state.response_times.lock().await.iter().sum();
+    let average_duration = total_duration
+        .checked_div(completed as u32)
+        .unwrap_or_default();
+
+    let success = *state.success_count.lock().await;
+    let errors = *state.error_count.lock().await;
+    let retried = *state.retried_count.lock().await;
+    let status_counts = state
+        .status_counts
+        .lock()
+        .await
+        .clone()
+        .into_iter()
+        .collect();
+
+    // Most frequent first, ties in a stable alphabetical order
+    let mut top_errors: Vec<ErrorCount> = state
+        .error_messages
+        .lock()
+        .await
+        .iter()
+        .map(|(message, &count)| ErrorCount {
+            message: message.clone(),
+            count,
         })
         .collect();
+    top_errors.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.message.cmp(&b.message))
+    });
+    top_errors.truncate(TOP_ERRORS);
+
+    let metric = match config.extract_metric {
+        Some(ref path) => {
+            let mut values = std::mem::take(&mut *state.metric_values.lock().await);
+            values.sort_by(f64::total_cmp);
+            Some(MetricReport {
+                path: path.clone(),
+                count: values.len(),
+                missing: *state.missing_metric_count.lock().await,
+                min: values.first().copied().unwrap_or_default(),
+                average: if values.is_empty() {
+                    0.0
+                } else {
+                    values.iter().sum::<f64>() / values.len() as f64
+                },
+                max: values.last().copied().unwrap_or_default(),
+                p95: percentile(&values, 95.0),
+            })
+        }
+        None => None,
+    };
 
-    // Wait for all tasks to complete
-    join_all(tasks).await;
+    // Without completed requests, e.g. after an early Ctrl-C, there's nothing to divide by
+    let (success_percentage, error_percentage) = if completed == 0 {
+        (0.0, 0.0)
+    } else {
+        (
+            success as f64 / completed as f64 * 100.0,
+            errors as f64 / completed as f64 * 100.0,
+        )
+    };
 
-    let total_duration: Duration = response_times.lock().await.iter().sum();
-    let average_duration = total_duration / config.request_count as u32;
+    let mut results = std::mem::take(&mut *state.results.lock().await);
+    results.sort_by_key(|r| r.index);
+    let apdex = config
+        .latency_budget_ms
+        .map(|ms| apdex(&results, Duration::from_millis(ms)));
 
-    let success = *success_count.lock().await;
-    let errors = *error_count.lock().await;
+    // A test cancelled before it started can take no measurable time
+    let per_sec = |amount: f64| match wall_elapsed.as_secs_f64() {
+        secs if secs > 0.0 => amount / secs,
+        _ => 0.0,
+    };
+    let throughput = per_sec(completed as f64);
 
-    let success_percentage = (success as f64 / config.request_count as f64) * 100.0;
-    let error_percentage = (errors as f64 / config.request_count as f64) * 100.0;
+    let response_sizes = state.response_sizes.lock().await;
+    let total_response_bytes: u64 = response_sizes.iter().sum();
+    let average_response_bytes = if response_sizes.is_empty() {
+        0.0
+    } else {
+        total_response_bytes as f64 / response_sizes.len() as f64
+    };
+    let transfer_rate = per_sec(total_response_bytes as f64 / 1_000_000.0);
+    let request_compression_ratio = match *state.request_body_sizes.lock().await {
+        (original, compressed) if compressed > 0 => Some(original as f64 / compressed as f64),
+        _ => None,
+    };
 
-    let response_times_locked = response_times.lock().await;
+    let mut response_times_locked = state.response_times.lock().await;
+    response_times_locked.sort();
     let duration = Duration::new(0, 0);
     let min_duration = response_times_locked.iter().min().unwrap_or(&duration);
     let max_duration = response_times_locked.iter().max().unwrap_or(&duration);
+    let mut first_byte_times: Vec<Duration> = results
+        .iter()
+        .filter_map(|r| r.time_to_first_byte)
+        .collect();
+    first_byte_times.sort();
+    let first_byte_percentile = |pct: f64| {
+        if first_byte_times.is_empty() {
+            None
+        } else {
+            Some(percentile(&first_byte_times, pct))
+        }
+    };
 
-    // Final statistics
-    println!("Total Requests: {}", config.request_count);
-    println!("Successful Requests: {}", success);
-    println!("Failed Requests: {}", errors);
-    println!("Success Percentage: {:.2}%", success_percentage);
-    println!("Failure Percentage: {:.2}%", error_percentage);
-    println!("Average Response Time: {:?}", average_duration);
-    println!("Minimum Response Time: {:?}", min_duration);
-    println!("Maximum Response Time: {:?}", max_duration);
+    let percentile_of = |pct: f64| {
+        if response_times_locked.is_empty() {
+            None
+        } else {
+            Some(percentile(&response_times_locked, pct))
+        }
+    };
 
-    Ok(())
+    TestReport {
+        total_requests: completed,
+        successful_requests: success,
+        failed_requests: errors,
+        timeout_errors: *state.timeout_error_count.lock().await,
+        connection_errors: *state.connection_error_count.lock().await,
+        http_errors: *state.http_error_count.lock().await,
+        oversize_responses: *state.oversize_response_count.lock().await,
+        header_assertion_failures: *state.header_assertion_failure_count.lock().await,
+        body_assertion_failures: *state.body_assertion_failure_count.lock().await,
+        other_errors: *state.other_error_count.lock().await,
+        retried_requests: retried,
+        dropped_requests: *state.dropped_count.lock().await,
+        status_counts,
+        status_classes: status_classes(&results),
+        top_errors,
+        success_percentage,
+        failure_percentage: error_percentage,
+        average_response_time: average_duration,
+        std_dev_response_time: std_dev(&response_times_locked),
+        min_response_time: *min_duration,
+        max_response_time: *max_duration,
+        p50_response_time: percentile_of(50.0),
+        p90_response_time: percentile_of(90.0),
+        p95_response_time: percentile_of(95.0),
+        p99_response_time: percentile_of(99.0),
+        average_time_to_first_byte: first_byte_times
+            .iter()
+            .sum::<Duration>()
+            .checked_div(first_byte_times.len() as u32)
+            .unwrap_or_default(),
+        p50_time_to_first_byte: first_byte_percentile(50.0),
+        p95_time_to_first_byte: first_byte_percentile(95.0),
+        p99_time_to_first_byte: first_byte_percentile(99.0),
+        throughput,
+        total_response_bytes,
+        min_response_bytes: response_sizes.iter().copied().min().unwrap_or_default(),
+        max_response_bytes: response_sizes.iter().copied().max().unwrap_or_default(),
+        average_response_bytes,
+        transfer_rate,
+        request_compression_ratio,
+        seed: config.seed.unwrap_or_default(),
+        pool_max_idle_per_host: config.pool_max_idle_per_host,
+        pool_idle_timeout_secs: config
+            .pool_idle_timeout_secs
+            .unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+        http_version: state
+            .http_version
+            .lock()
+            .await
+            .map(|version| format!("{:?}", version)),
+        metric,
+        apdex,
+        scenarios: scenario_reports(config, &results),
+        urls: url_reports(config, targets, &results),
+        methods: method_reports(config, targets, &results),
+        traffic_profile: traffic_profile_report(config, &state.profile_starts.lock().await),
+        stages: stage_reports(config, &results),
+        groups: group_by_reports(config, &results),
+        results,
+        interrupted: state.cancelled.load(Ordering::SeqCst),
+        max_duration_reached,
+        circuit_breaker_tripped_after: state
+            .circuit_breaker_tripped
+            .load(Ordering::SeqCst)
+            .then_some(config.abort_after_consecutive_failures)
+            .flatten(),
+        sla_p95_ms: config.sla_p95_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stages() -> Vec<Stage> {
+        vec![
+            Stage {
+                duration_secs: 10,
+                target_concurrency: 10,
+            },
+            Stage {
+                duration_secs: 20,
+                target_concurrency: 30,
+            },
+        ]
+    }
+
+    #[test]
+    fn stage_at_the_start_is_the_first_at_zero_concurrency() {
+        assert_eq!(stage_at(&stages(), Duration::ZERO), Some((0, 0)));
+    }
+
+    #[test]
+    fn stage_boundary_belongs_to_the_next_stage() {
+        let stages = stages();
+        let boundary = Duration::from_secs(10);
+        let just_before = boundary - Duration::from_millis(1);
+        assert_eq!(stage_at(&stages, just_before), Some((0, 10)));
+        // The next stage starts from the previous target
+        assert_eq!(stage_at(&stages, boundary), Some((1, 10)));
+        assert_eq!(stage_at(&stages, Duration::from_secs(20)), Some((1, 20)));
+    }
+
+    #[test]
+    fn stage_after_the_last_one_is_none() {
+        let stages = stages();
+        let end = Duration::from_secs(30);
+        let just_before = end - Duration::from_millis(1);
+        assert_eq!(stage_at(&stages, just_before), Some((1, 30)));
+        assert_eq!(stage_at(&stages, end), None);
+        assert_eq!(stage_at(&[], Duration::ZERO), None);
+    }
+
+    fn built_url(query: &[(&str, &str)], data_row: Option<Row>) -> String {
+        let config = LoadTestConfig {
+            url: "http://localhost/search".to_string(),
+            query: Some(
+                query
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let request = build_request(
+            &config,
+            &reqwest::Client::new(),
+            0,
+            &data_row,
+            &HashMap::new(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        request.url().to_string()
+    }
+
+    #[test]
+    fn query_values_are_percent_encoded() {
+        assert_eq!(
+            built_url(&[("q", "a&b c=d#e")], None),
+            "http://localhost/search?q=a%26b+c%3Dd%23e"
+        );
+        assert_eq!(
+            built_url(&[("name", "Zoë 東京")], None),
+            "http://localhost/search?name=Zo%C3%AB+%E6%9D%B1%E4%BA%AC"
+        );
+        assert_eq!(
+            built_url(&[("a&b", "1")], None),
+            "http://localhost/search?a%26b=1"
+        );
+    }
+
+    #[test]
+    fn substituted_query_values_are_percent_encoded() {
+        let row = HashMap::from([("term".to_string(), "x&y=z".into())]);
+        assert_eq!(
+            built_url(&[("q", "${term}")], Some(row)),
+            "http://localhost/search?q=x%26y%3Dz"
+        );
+    }
+
+    // A payload_dir with ok.bin inside it and secret.txt next to it
+    fn payload_fixture() -> (std::path::PathBuf, String) {
+        let root = std::env::temp_dir().join(format!("loadfire-{}", uuid::Uuid::new_v4()));
+        let dir = root.join("payloads");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ok.bin"), [0, 159, 146, 150]).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        (root, dir)
+    }
+
+    #[test]
+    fn body_from_row_keeps_nested_values() {
+        let config = LoadTestConfig {
+            url: "http://localhost/users".to_string(),
+            body_from_row: Some(true),
+            ..Default::default()
+        };
+        let data_row = serde_json::from_value(serde_json::json!({
+            "user": {"name": "alice", "roles": ["admin"]},
+            "age": 30,
+            "active": true,
+            "_id": "7",
+        }))
+        .unwrap();
+        let request = build_request(
+            &config,
+            &reqwest::Client::new(),
+            0,
+            &with_index(Some(data_row), 0),
+            &HashMap::new(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "user": {"name": "alice", "roles": ["admin"]},
+                "age": 30,
+                "active": true,
+            })
+        );
+    }
+
+    fn row(value: &str) -> Option<Row> {
+        Some(HashMap::from([("file".to_string(), value.into())]))
+    }
+
+    #[tokio::test]
+    async fn payloads_are_read_from_payload_dir() {
+        let (root, dir) = payload_fixture();
+        let payloads = read_payloads(Some(&dir), &row("@ok.bin")).await.unwrap();
+        assert_eq!(payloads["file"], [0, 159, 146, 150]);
+        // Without a payload_dir an @ value is plain text
+        assert!(read_payloads(None, &row("@ok.bin"))
+            .await
+            .unwrap()
+            .is_empty());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn payloads_outside_payload_dir_are_rejected() {
+        let (root, dir) = payload_fixture();
+        let outside = root.join("secret.txt").to_string_lossy().into_owned();
+        for value in [
+            "@../secret.txt",
+            "@./../payloads/../secret.txt",
+            &format!("@{}", outside),
+        ] {
+            let error = read_payloads(Some(&dir), &row(value)).await.unwrap_err();
+            assert!(
+                error.to_string().contains("is outside payload_dir"),
+                "{}: {}",
+                value,
+                error
+            );
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_without_completed_requests_has_no_nan_or_panic() {
+        let config = LoadTestConfig {
+            url: "http://localhost/".to_string(),
+            request_count: Some(0),
+            latency_budget_ms: Some(100),
+            extract_metric: Some("timing.server_ms".to_string()),
+            ..Default::default()
+        };
+        let targets = Targets::new(&config).unwrap();
+        let state = LoadTestState::default();
+
+        // Both with and without elapsed time, rates divide by it
+        for elapsed in [Duration::ZERO, Duration::from_secs(1)] {
+            let report = build_report(&config, &targets, &state, elapsed, false).await;
+            assert_eq!(report.total_requests, 0);
+            assert_eq!(report.success_percentage, 0.0);
+            assert_eq!(report.failure_percentage, 0.0);
+            assert_eq!(report.throughput, 0.0);
+            assert_eq!(report.transfer_rate, 0.0);
+            assert_eq!(report.average_response_bytes, 0.0);
+            assert_eq!(report.average_response_time, Duration::ZERO);
+            assert_eq!(report.std_dev_response_time, Duration::ZERO);
+            assert_eq!(report.average_time_to_first_byte, Duration::ZERO);
+            assert_eq!(report.min_response_time, Duration::ZERO);
+            assert_eq!(report.max_response_time, Duration::ZERO);
+            assert_eq!(report.p50_response_time, None);
+            assert_eq!(report.p99_response_time, None);
+            assert_eq!(report.p95_time_to_first_byte, None);
+            assert_eq!(report.request_compression_ratio, None);
+            assert_eq!(report.apdex.map(|apdex| apdex.score), Some(0.0));
+            assert!(report.status_classes.iter().all(|c| c.percentage == 0.0));
+
+            let metric = report.metric.unwrap();
+            assert_eq!(metric.count, 0);
+            assert_eq!(metric.average, 0.0);
+            assert_eq!(metric.p95, 0.0);
+        }
+    }
 }
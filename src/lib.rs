@@ -0,0 +1,46 @@
+mod cancel;
+pub mod config;
+mod data;
+mod har;
+mod html;
+mod http;
+mod limiter;
+mod prometheus;
+pub mod report;
+mod utils;
+mod websocket;
+
+pub use cancel::CancelToken;
+pub use config::{load_config, validate_config, LoadTestConfig};
+pub use report::TestReport;
+pub use utils::format_duration;
+
+/// Runs a load test with the given configuration and returns its statistics
+pub async fn run_load_test(
+    config: LoadTestConfig,
+) -> Result<TestReport, Box<dyn std::error::Error>> {
+    http::perform_load_test(&config, None, CancelToken::default()).await
+}
+
+/// Like `run_load_test`, but also sends every completed request to `sender` as it finishes
+pub async fn run_load_test_streaming(
+    config: LoadTestConfig,
+    sender: tokio::sync::mpsc::UnboundedSender<report::RequestResult>,
+) -> Result<TestReport, Box<dyn std::error::Error>> {
+    http::perform_load_test(&config, Some(sender), CancelToken::default()).await
+}
+
+/// Like `run_load_test`, but stops early once `cancel` is cancelled, for example from a
+/// Ctrl-C handler, and sends every completed request to `sender` when one is given
+pub async fn run_load_test_cancellable(
+    config: LoadTestConfig,
+    sender: Option<tokio::sync::mpsc::UnboundedSender<report::RequestResult>>,
+    cancel: CancelToken,
+) -> Result<TestReport, Box<dyn std::error::Error>> {
+    http::perform_load_test(&config, sender, cancel).await
+}
+
+/// Loads the data file and renders the first request without sending it
+pub async fn dry_run(config: &LoadTestConfig) -> Result<String, Box<dyn std::error::Error>> {
+    http::render_sample_request(config).await
+}
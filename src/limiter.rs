@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::Mutex,
+    time::{interval_at, Instant, Interval, MissedTickBehavior},
+};
+
+// Paces callers so that at most `rps` of them proceed per second
+pub struct RateLimiter {
+    interval: Mutex<Interval>,
+}
+
+impl RateLimiter {
+    pub fn new(rps: u32) -> Self {
+        // The first slot opens one period in, so n requests take at least n / rps seconds
+        let period = Duration::from_secs(1) / rps;
+        let mut interval = interval_at(Instant::now() + period, period);
+        // Never catch up on missed ticks with a burst above the configured rate
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        RateLimiter {
+            interval: Mutex::new(interval),
+        }
+    }
+
+    // Waits until the next request slot is released
+    pub async fn acquire(&self) {
+        self.interval.lock().await.tick().await;
+    }
+}
@@ -1,31 +1,594 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use clap::Parser;
-use config::load_config;
-use http::perform_load_test;
+use loadfire::{
+    dry_run, format_duration, load_config,
+    report::{
+        ndjson_record, print_report, write_html_report, write_prometheus_textfile,
+        write_timings_csv, OutputFormat, TimeUnit,
+    },
+    run_load_test_cancellable, validate_config, CancelToken, LoadTestConfig, TestReport,
+};
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
 
-mod config;
-mod data;
-mod http;
-mod utils;
+// How long the config files must stay unchanged before a watched test reruns
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
 
 /// Loadfire load testing tool
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the YAML configuration file
-    #[clap(short, long, value_parser)]
-    config: String,
+    /// Path to the YAML, TOML, or JSON configuration file, repeat to run several tests in a row
+    #[clap(short, long, value_parser, required_unless_present = "har")]
+    config: Vec<String>,
+
+    /// Replay the requests of a HAR recording instead of a configuration file
+    #[clap(long, value_name = "PATH", conflicts_with = "config")]
+    har: Option<String>,
+
+    /// Only replay the HAR's requests to this host, repeat for several hosts
+    #[clap(long, value_name = "HOST", requires = "har")]
+    har_host: Vec<String>,
+
+    /// Keep running the remaining configs after one fails
+    #[clap(long)]
+    continue_on_error: bool,
+
+    /// Format of the final report
+    #[clap(short, long, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// Unit of the latencies in the text and HTML reports
+    #[clap(long, value_enum, default_value = "ms")]
+    time_unit: TimeUnit,
+
+    /// Write the HTML report to this file instead of stdout
+    #[clap(long, value_parser)]
+    report_out: Option<String>,
+
+    /// Write the Prometheus metrics to this file instead of stdout, e.g. for node_exporter
+    #[clap(long, value_parser)]
+    textfile_out: Option<String>,
+
+    /// Log each request (-v) and more detail (-vv, -vvv)
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Exit with code 1 if the failure percentage exceeds this value
+    #[clap(long, value_name = "PERCENT")]
+    fail_over: Option<f64>,
+
+    /// Exit with code 1 if the p99 response time exceeds this many milliseconds
+    #[clap(long, value_name = "MS")]
+    fail_if_p99_over: Option<f64>,
+
+    /// Validate the config and print a sample request without sending traffic
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Write every request's timing to this CSV file
+    #[clap(long, value_parser)]
+    timings_out: Option<String>,
+
+    /// Hide the progress bar, e.g. in CI logs
+    #[clap(long)]
+    no_progress: bool,
+
+    /// Print only one tab-separated line: requests, successes, p50, p95, p99 (ms) and req/s
+    #[clap(long, conflicts_with = "output")]
+    quiet: bool,
+
+    /// Start without checking that the target is reachable
+    #[clap(long)]
+    skip_preflight: bool,
+
+    /// Save this many responses, failing ones first, to the sample directory
+    #[clap(long, value_name = "N")]
+    sample_responses: Option<usize>,
+
+    /// Directory for the saved responses
+    #[clap(long, value_name = "PATH")]
+    sample_dir: Option<String>,
+
+    /// Send this many requests, overriding request_count and duration_secs
+    #[clap(long, value_name = "N")]
+    count: Option<usize>,
+
+    /// Stop the test after this many seconds however many requests are left
+    #[clap(long, value_name = "SECS")]
+    max_duration_secs: Option<u64>,
+
+    /// Run this many requests at once, overriding concurrency
+    #[clap(long, value_name = "N")]
+    concurrency: Option<usize>,
+
+    /// Run each config this many times and report the run-to-run variance
+    #[clap(long, value_name = "K", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// Rerun the tests whenever a config file is saved, until Ctrl-C
+    #[clap(long)]
+    watch: bool,
+
+    /// Pause between repeated runs
+    #[clap(long, value_name = "SECS", default_value_t = 5)]
+    repeat_cooldown_secs: u64,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
-    match load_config(&args.config) {
-        Ok(config) => {
-            if let Err(e) = perform_load_test(&config).await {
-                eprintln!("Error during load test: {}", e);
+    // RUST_LOG, when set, takes precedence over the verbosity flag
+    let level = match args.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_module("loadfire", level)
+        .parse_default_env()
+        .init();
+
+    if args.dry_run {
+        for path in &sources(&args) {
+            let config = match prepare_config(&args, path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            match dry_run(&config).await {
+                Ok(sample) => print!("{}", sample),
+                Err(e) => {
+                    eprintln!("Dry run failed for {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    // The only Ctrl-C handler: the first press stops the running test and any runs still to
+    // come, a second one exits right away
+    let cancel = CancelToken::default();
+    {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+    }
+
+    if args.watch {
+        watch(&args, &cancel).await;
+        return;
+    }
+
+    let mut suite = Vec::new();
+    let sources = sources(&args);
+    'configs: for path in &sources {
+        let mut runs = Vec::new();
+        for run in 1..=args.repeat {
+            if run > 1 {
+                tokio::select! {
+                    _ = cancel.cancelled() => break 'configs,
+                    _ = tokio::time::sleep(Duration::from_secs(args.repeat_cooldown_secs)) => {}
+                }
+            }
+            let name = match args.repeat {
+                1 => path.clone(),
+                _ => format!("{} #{}", path, run),
+            };
+            if (sources.len() > 1 || args.repeat > 1) && !args.quiet {
+                eprintln!("Running {}", name);
+            }
+
+            let (report, passed) = run_config(&args, path, &cancel).await;
+            if let Some(ref report) = report {
+                runs.push(report.clone());
+            }
+            suite.push((name, report, passed));
+            if cancel.is_cancelled() || (!passed && !args.continue_on_error) {
+                break 'configs;
+            }
+        }
+        if args.repeat > 1 && !args.quiet {
+            print_repeat_summary(path, &runs, args.output, args.time_unit);
+        }
+    }
+
+    if suite.len() > 1 && !args.quiet {
+        print_suite_summary(&suite, args.output, args.time_unit);
+    }
+    if cancel.is_cancelled() {
+        std::process::exit(130);
+    }
+    if suite.iter().any(|(_, _, passed)| !passed) {
+        std::process::exit(1);
+    }
+}
+
+// Runs the configs, then again each time one of them changes. The directories are watched
+// rather than the files, since editors often save by replacing the file.
+async fn watch(args: &Args, cancel: &CancelToken) {
+    let sources = sources(args);
+    let files: Vec<PathBuf> = sources
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .canonicalize()
+                .unwrap_or_else(|_| PathBuf::from(path))
+        })
+        .collect();
+
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let watched = files.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if !event.kind.is_access() && event.paths.iter().any(|p| watched.contains(p)) {
+            let _ = sender.send(());
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Failed to watch the config files: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for file in &files {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            eprintln!("Failed to watch {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    loop {
+        if std::io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+        }
+        for path in &sources {
+            run_config(args, path, cancel).await;
+            if cancel.is_cancelled() {
+                break;
+            }
+        }
+        // Ctrl-C during a run stops that test, and the watch along with it
+        if cancel.is_cancelled() {
+            return;
+        }
+        eprintln!("Watching for changes, press Ctrl-C to stop");
+
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            change = changes.recv() => {
+                if change.is_none() {
+                    return;
+                }
+            }
+        }
+        // Saving can touch a file several times, wait until it settles
+        while let Ok(Some(())) = tokio::time::timeout(WATCH_DEBOUNCE, changes.recv()).await {}
+    }
+}
+
+// The config files to run, or the recording when --har is given
+fn sources(args: &Args) -> Vec<String> {
+    match args.har {
+        Some(ref har) => vec![har.clone()],
+        None => args.config.clone(),
+    }
+}
+
+// Loads a config file, or for --har a config that replays the recording once. Both can be
+// scaled with the override flags like --count and --concurrency.
+fn read_config(args: &Args, path: &str) -> Result<LoadTestConfig, Box<dyn std::error::Error>> {
+    if args.har.is_none() {
+        return load_config(path);
+    }
+    Ok(LoadTestConfig {
+        har_file: Some(path.to_string()),
+        har_hosts: (!args.har_host.is_empty()).then(|| args.har_host.clone()),
+        request_count: Some(1),
+        ..Default::default()
+    })
+}
+
+// Reads a config and applies the command-line overrides, then validates the result, so a
+// config may leave for example the request count to --count
+fn prepare_config(args: &Args, path: &str) -> Result<LoadTestConfig, String> {
+    let mut config =
+        read_config(args, path).map_err(|e| format!("Failed to read config {}: {}", path, e))?;
+
+    // Command-line flags take precedence over the config file
+    if args.no_progress || args.quiet {
+        config.progress = Some(false);
+    }
+    if args.skip_preflight {
+        config.preflight = Some(false);
+    }
+    if let Some(samples) = args.sample_responses {
+        config.sample_responses = Some(samples);
+    }
+    if let Some(ref dir) = args.sample_dir {
+        config.sample_dir = Some(dir.clone());
+    }
+    if let Some(count) = args.count {
+        config.request_count = Some(count);
+        config.duration_secs = None;
+    }
+    if let Some(concurrency) = args.concurrency {
+        config.concurrency = Some(concurrency);
+    }
+    if let Some(secs) = args.max_duration_secs {
+        config.max_duration_secs = Some(secs);
+    }
+    if let Err(errors) = validate_config(&config) {
+        let list: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+        return Err(format!(
+            "Invalid configuration {}:\n{}",
+            path,
+            list.join("\n")
+        ));
+    }
+    Ok(config)
+}
+
+// Runs the test of one config file and writes its outputs. Returns the report, if the
+// test ran, and whether it passed without errors or threshold violations.
+async fn run_config(args: &Args, path: &str, cancel: &CancelToken) -> (Option<TestReport>, bool) {
+    let config = match prepare_config(args, path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return (None, false);
+        }
+    };
+
+    let report = match run(config, args.output, cancel.clone()).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error during load test: {}", e);
+            return (None, false);
+        }
+    };
+
+    match (args.output, &args.report_out, &args.textfile_out) {
+        (OutputFormat::Html, Some(out), _) => {
+            let out = output_path(args, out, path);
+            if let Err(e) = write_html_report(&report, &out, args.time_unit) {
+                eprintln!("Failed to write report to {}: {}", out, e);
+                return (Some(report), false);
+            }
+        }
+        (OutputFormat::Prometheus, _, Some(out)) => {
+            let out = output_path(args, out, path);
+            if let Err(e) = write_prometheus_textfile(&report, &out) {
+                eprintln!("Failed to write metrics to {}: {}", out, e);
+                return (Some(report), false);
+            }
+        }
+        _ if args.quiet => println!("{}", summary_line(&report, args.time_unit)),
+        _ => {
+            if let Err(e) = print_report(&report, args.output, args.time_unit) {
+                eprintln!("Failed to print report: {}", e);
             }
         }
-        Err(e) => eprintln!("Failed to read config: {}", e),
     }
+
+    if let Some(ref out) = args.timings_out {
+        let out = output_path(args, out, path);
+        if let Err(e) = write_timings_csv(&report.results, &out) {
+            eprintln!("Failed to write timings to {}: {}", out, e);
+            return (Some(report), false);
+        }
+    }
+
+    let violations = threshold_violations(args, &report);
+    for violation in &violations {
+        eprintln!("Threshold exceeded: {}", violation);
+    }
+    (Some(report), violations.is_empty())
+}
+
+// With several configs each test gets its own output file, e.g. report-users.html
+fn output_path(args: &Args, out: &str, config_path: &str) -> String {
+    if args.config.len() < 2 {
+        return out.to_string();
+    }
+
+    let out = Path::new(out);
+    let config_name = Path::new(config_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("config");
+    let stem = out.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let file_name = match out.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}-{}.{}", stem, config_name, extension),
+        None => format!("{}-{}", stem, config_name),
+    };
+    out.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+// Compares the tests of a suite side by side, on stderr unless the output is text
+fn print_suite_summary(
+    suite: &[(String, Option<TestReport>, bool)],
+    output: OutputFormat,
+    unit: TimeUnit,
+) {
+    let width = suite
+        .iter()
+        .map(|(path, _, _)| path.len())
+        .max()
+        .unwrap_or_default()
+        .max("Config".len());
+
+    let mut lines = vec![
+        String::new(),
+        "Suite Summary:".to_string(),
+        format!(
+            "{:<width$}  {:>10}  {:>9}  {:>12}  Result",
+            "Config", "Requests", "Success", "P95"
+        ),
+    ];
+    for (path, report, passed) in suite {
+        let result = if *passed { "passed" } else { "FAILED" };
+        lines.push(match report {
+            Some(report) => format!(
+                "{:<width$}  {:>10}  {:>8.2}%  {:>12}  {}",
+                path,
+                report.total_requests,
+                report.success_percentage,
+                report
+                    .p95_response_time
+                    .map(|p95| format_duration(p95, unit))
+                    .unwrap_or_else(|| "N/A".to_string()),
+                result
+            ),
+            None => format!(
+                "{:<width$}  {:>10}  {:>9}  {:>12}  {}",
+                path, "-", "-", "-", result
+            ),
+        });
+    }
+
+    for line in lines {
+        if output == OutputFormat::Text {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+// The --quiet output, one line per test for scripts to parse. Latencies are numbers in the
+// time unit and missing ones, when no request succeeded, are printed as "-".
+fn summary_line(report: &TestReport, unit: TimeUnit) -> String {
+    let latency = |latency: Option<Duration>| {
+        latency
+            .map(|latency| format!("{:.*}", unit.precision(), unit.convert(latency)))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{:.2}",
+        report.total_requests,
+        report.successful_requests,
+        latency(report.p50_response_time),
+        latency(report.p95_response_time),
+        latency(report.p99_response_time),
+        report.throughput
+    )
+}
+
+// Mean and sample standard deviation of the key metrics across repeated runs of a config
+fn print_repeat_summary(path: &str, runs: &[TestReport], output: OutputFormat, unit: TimeUnit) {
+    let throughputs: Vec<f64> = runs.iter().map(|r| r.throughput).collect();
+    let p95s: Vec<f64> = runs
+        .iter()
+        .filter_map(|r| r.p95_response_time)
+        .map(|p95| unit.convert(p95))
+        .collect();
+    let (throughput_mean, throughput_std_dev) = mean_std_dev(&throughputs);
+    let (p95_mean, p95_std_dev) = mean_std_dev(&p95s);
+
+    let lines = [
+        String::new(),
+        format!("Repeat Summary for {} ({} runs):", path, runs.len()),
+        format!(
+            "  Throughput: {:.2} ± {:.2} req/s",
+            throughput_mean, throughput_std_dev
+        ),
+        format!(
+            "  P95 Response Time: {:.*} ± {:.*} {}",
+            unit.precision(),
+            p95_mean,
+            unit.precision(),
+            p95_std_dev,
+            unit.suffix()
+        ),
+    ];
+    for line in lines {
+        if output == OutputFormat::Text {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+fn mean_std_dev(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance =
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    (mean, variance.sqrt())
+}
+
+// Runs the test, printing each request as a JSON line while it runs for ndjson output
+async fn run(
+    config: LoadTestConfig,
+    output: OutputFormat,
+    cancel: CancelToken,
+) -> Result<TestReport, Box<dyn std::error::Error>> {
+    if output != OutputFormat::Ndjson {
+        return run_load_test_cancellable(config, None, cancel).await;
+    }
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(async move {
+        while let Some(result) = receiver.recv().await {
+            println!("{}", ndjson_record(&result));
+        }
+    });
+
+    // The sender is dropped when the test ends, which lets the writer drain and finish
+    let report = run_load_test_cancellable(config, Some(sender), cancel).await;
+    let _ = writer.await;
+    report
+}
+
+// Checks the report against the CI gating flags
+fn threshold_violations(args: &Args, report: &TestReport) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if let Some(max_failure) = args.fail_over {
+        if report.failure_percentage > max_failure {
+            violations.push(format!(
+                "failure percentage {:.2}% is over {:.2}%",
+                report.failure_percentage, max_failure
+            ));
+        }
+    }
+
+    if let (Some(max_p99), Some(p99)) = (args.fail_if_p99_over, report.p99_response_time) {
+        let p99_ms = p99.as_secs_f64() * 1000.0;
+        if p99_ms > max_p99 {
+            violations.push(format!(
+                "p99 response time {:.2}ms is over {:.2}ms",
+                p99_ms, max_p99
+            ));
+        }
+    }
+
+    violations
 }
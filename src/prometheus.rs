@@ -0,0 +1,175 @@
+use std::fmt::Write;
+
+use crate::report::TestReport;
+
+// Renders the report in the Prometheus text exposition format, e.g. for the node_exporter
+// textfile collector
+pub fn render_prometheus(report: &TestReport) -> String {
+    let mut out = String::new();
+
+    let counters = [
+        (
+            "loadfire_requests_total",
+            "Requests completed during the test",
+            report.total_requests as f64,
+        ),
+        (
+            "loadfire_successes_total",
+            "Requests that met the success criteria",
+            report.successful_requests as f64,
+        ),
+        (
+            "loadfire_failures_total",
+            "Requests that failed for any reason",
+            report.failed_requests as f64,
+        ),
+        (
+            "loadfire_retried_requests_total",
+            "Requests that needed at least one retry",
+            report.retried_requests as f64,
+        ),
+        (
+            "loadfire_dropped_requests_total",
+            "Scheduled requests skipped because every slot was taken",
+            report.dropped_requests as f64,
+        ),
+        (
+            "loadfire_oversize_responses_total",
+            "Response bodies cut off at max_body_bytes",
+            report.oversize_responses as f64,
+        ),
+        (
+            "loadfire_response_bytes_total",
+            "Response body bytes received, after decompression",
+            report.total_response_bytes as f64,
+        ),
+    ];
+    for (name, help, value) in counters {
+        write_metric(&mut out, name, help, "counter", &[(String::new(), value)]);
+    }
+
+    let errors = [
+        ("timeout", report.timeout_errors),
+        ("connection", report.connection_errors),
+        ("http", report.http_errors),
+        ("header_assertion", report.header_assertion_failures),
+        ("body_assertion", report.body_assertion_failures),
+        ("other", report.other_errors),
+    ];
+    let errors: Vec<(String, f64)> = errors
+        .iter()
+        .map(|(kind, count)| (format!("kind=\"{}\"", kind), *count as f64))
+        .collect();
+    write_metric(
+        &mut out,
+        "loadfire_errors_total",
+        "Failed requests by kind",
+        "counter",
+        &errors,
+    );
+
+    let statuses: Vec<(String, f64)> = report
+        .status_counts
+        .iter()
+        .map(|(status, count)| (format!("code=\"{}\"", status), *count as f64))
+        .collect();
+    write_metric(
+        &mut out,
+        "loadfire_responses_total",
+        "Responses by HTTP status code",
+        "counter",
+        &statuses,
+    );
+
+    let quantiles: Vec<(String, f64)> = [
+        ("0.5", report.p50_response_time),
+        ("0.9", report.p90_response_time),
+        ("0.95", report.p95_response_time),
+        ("0.99", report.p99_response_time),
+    ]
+    .iter()
+    .filter_map(|(quantile, value)| {
+        value.map(|d| (format!("quantile=\"{}\"", quantile), d.as_secs_f64()))
+    })
+    .collect();
+    write_metric(
+        &mut out,
+        "loadfire_latency_seconds",
+        "Response times of completed requests",
+        "summary",
+        &quantiles,
+    );
+    let _ = writeln!(
+        out,
+        "loadfire_latency_seconds_sum {}",
+        report.average_response_time.as_secs_f64() * report.total_requests as f64
+    );
+    let _ = writeln!(
+        out,
+        "loadfire_latency_seconds_count {}",
+        report.total_requests
+    );
+
+    let first_byte_quantiles: Vec<(String, f64)> = [
+        ("0.5", report.p50_time_to_first_byte),
+        ("0.95", report.p95_time_to_first_byte),
+        ("0.99", report.p99_time_to_first_byte),
+    ]
+    .iter()
+    .filter_map(|(quantile, value)| {
+        value.map(|d| (format!("quantile=\"{}\"", quantile), d.as_secs_f64()))
+    })
+    .collect();
+    write_metric(
+        &mut out,
+        "loadfire_time_to_first_byte_seconds",
+        "Time until the response headers arrived, by quantile",
+        "gauge",
+        &first_byte_quantiles,
+    );
+
+    let gauges = [
+        (
+            "loadfire_throughput_requests_per_second",
+            "Completed requests per second of wall-clock time",
+            report.throughput,
+        ),
+        (
+            "loadfire_success_ratio",
+            "Share of requests that succeeded, from 0 to 1",
+            report.success_percentage / 100.0,
+        ),
+        (
+            "loadfire_interrupted",
+            "1 if the test was stopped early, 0 otherwise",
+            f64::from(u8::from(report.interrupted)),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        write_metric(&mut out, name, help, "gauge", &[(String::new(), value)]);
+    }
+    if let Some(ref apdex) = report.apdex {
+        write_metric(
+            &mut out,
+            "loadfire_apdex_score",
+            "Apdex score against latency_budget_ms, from 0 to 1",
+            "gauge",
+            &[(String::new(), apdex.score)],
+        );
+    }
+
+    out
+}
+
+// HELP and TYPE lines followed by one sample per label set
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
@@ -0,0 +1,612 @@
+use std::{collections::BTreeMap, io::IsTerminal, time::Duration};
+
+use serde::{Serialize, Serializer};
+
+use crate::{
+    html::render_html_report,
+    prometheus::render_prometheus,
+    utils::{format_duration, format_opt_duration, histogram},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Html,
+    Ndjson,
+    Prometheus,
+}
+
+// Unit of the latencies in the text and HTML reports, the JSON report always uses milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimeUnit {
+    Us,
+    #[default]
+    Ms,
+    S,
+}
+
+impl TimeUnit {
+    pub fn convert(self, duration: Duration) -> f64 {
+        match self {
+            TimeUnit::Us => duration.as_secs_f64() * 1_000_000.0,
+            TimeUnit::Ms => duration.as_secs_f64() * 1000.0,
+            TimeUnit::S => duration.as_secs_f64(),
+        }
+    }
+
+    // Decimals shown, whole microseconds and otherwise down to 10µs or 1ms
+    pub fn precision(self) -> usize {
+        match self {
+            TimeUnit::Us => 0,
+            TimeUnit::Ms => 2,
+            TimeUnit::S => 3,
+        }
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TimeUnit::Us => "us",
+            TimeUnit::Ms => "ms",
+            TimeUnit::S => "s",
+        }
+    }
+}
+
+// Outcome of a single measured request
+#[derive(Debug, Serialize, Clone)]
+pub struct RequestResult {
+    pub index: usize,
+    pub status: Option<u16>,
+    #[serde(serialize_with = "serialize_ms")]
+    pub elapsed: Duration,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub time_to_first_byte: Option<Duration>, // Until the response headers arrived, None without a response
+    pub error: Option<String>,
+    pub success: bool,
+    pub scenario: Option<String>,
+    pub scenario_index: Option<usize>, // Position of the scenario or chain step, names can repeat
+    pub stage: Option<usize>,          // Index of the stage the request was sent in
+    pub group: Option<String>,         // Value of the group_by column in the request's data row
+    pub request_id: Option<String>,    // Sent in the inject_request_id_header header
+}
+
+// Statistics of a single weighted scenario
+#[derive(Debug, Serialize, Clone)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    #[serde(serialize_with = "serialize_ms")]
+    pub average_response_time: Duration,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p95_response_time: Option<Duration>,
+}
+
+// Aggregate of a number reported in the response bodies, such as server-side timing
+#[derive(Debug, Serialize, Clone)]
+pub struct MetricReport {
+    pub path: String,
+    pub count: usize,
+    pub missing: usize, // Successful responses without a number at the path
+    pub min: f64,
+    pub average: f64,
+    pub max: f64,
+    pub p95: f64,
+}
+
+// Share of requests meeting a latency budget, see utils::apdex
+#[derive(Debug, Serialize, Clone)]
+pub struct ApdexReport {
+    pub budget_ms: u64,
+    pub satisfied: usize,
+    pub tolerating: usize,
+    pub frustrated: usize,
+    pub score: f64,
+}
+
+// Requests whose status falls in one class, see utils::status_classes
+#[derive(Debug, Serialize, Clone)]
+pub struct StatusClassCount {
+    pub class: String, // 2xx to 5xx, or network error for requests without a response
+    pub count: usize,
+    pub percentage: f64,
+}
+
+// Rate achieved between two points of a traffic profile
+#[derive(Debug, Serialize, Clone)]
+pub struct RateBucket {
+    pub start_secs: u64,
+    pub end_secs: u64,
+    pub target_rps: f64, // Average of the interpolated rate over the bucket
+    pub achieved_rps: f64,
+}
+
+// A distinct failure reason and how often it occurred
+#[derive(Debug, Serialize, Clone)]
+pub struct ErrorCount {
+    pub message: String,
+    pub count: usize,
+}
+
+// Final statistics of a load test, durations are serialized as milliseconds
+#[derive(Debug, Serialize, Clone)]
+pub struct TestReport {
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    pub timeout_errors: usize,
+    pub connection_errors: usize,
+    pub http_errors: usize, // Responses that didn't meet the success criterion
+    pub header_assertion_failures: usize,
+    pub body_assertion_failures: usize,
+    pub other_errors: usize,
+    pub retried_requests: usize,
+    pub dropped_requests: usize, // Arrival-rate starts skipped because every slot was taken
+    pub oversize_responses: usize, // Bodies cut off at max_body_bytes, not counted as failures
+    pub status_counts: BTreeMap<u16, usize>,
+    pub status_classes: Vec<StatusClassCount>, // Rollup of status_counts and network errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub top_errors: Vec<ErrorCount>, // Most frequent failure reasons, at most 5
+    pub success_percentage: f64,
+    pub failure_percentage: f64,
+    #[serde(serialize_with = "serialize_ms")]
+    pub average_response_time: Duration,
+    #[serde(serialize_with = "serialize_ms")]
+    pub std_dev_response_time: Duration,
+    #[serde(serialize_with = "serialize_ms")]
+    pub min_response_time: Duration,
+    #[serde(serialize_with = "serialize_ms")]
+    pub max_response_time: Duration,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p50_response_time: Option<Duration>,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p90_response_time: Option<Duration>,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p95_response_time: Option<Duration>,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p99_response_time: Option<Duration>,
+    #[serde(serialize_with = "serialize_ms")]
+    pub average_time_to_first_byte: Duration, // Server latency without the body transfer
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p50_time_to_first_byte: Option<Duration>,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p95_time_to_first_byte: Option<Duration>,
+    #[serde(serialize_with = "serialize_opt_ms")]
+    pub p99_time_to_first_byte: Option<Duration>,
+    pub throughput: f64,           // Requests per second of wall-clock time
+    pub total_response_bytes: u64, // Response body bytes, after decompression
+    pub min_response_bytes: u64,
+    pub max_response_bytes: u64,
+    pub average_response_bytes: f64,
+    pub transfer_rate: f64, // Response body megabytes per second of wall-clock time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_compression_ratio: Option<f64>, // Body bytes before compress_request per byte sent
+    pub seed: u64, // Set it as the config's seed to replay the same data rows and random values
+    pub http_version: Option<String>, // Protocol of the first response, None if nothing was received
+    pub pool_max_idle_per_host: Option<usize>, // None when unlimited
+    pub pool_idle_timeout_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<MetricReport>, // Set when extract_metric is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apdex: Option<ApdexReport>, // Set when latency_budget_ms is configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scenarios: Vec<ScenarioReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<ScenarioReport>, // Same statistics per URL of a url_list_file test
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<ScenarioReport>, // Same statistics per method of a weighted method mix
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub traffic_profile: Vec<RateBucket>, // Target and achieved rate between profile points
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<ScenarioReport>, // Same statistics per stage of a staged test
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ScenarioReport>, // Same statistics per value of the group_by column, slowest first
+    #[serde(skip)]
+    pub results: Vec<RequestResult>,
+    pub interrupted: bool, // Stopped early by Ctrl-C, the circuit breaker or max_duration_secs, statistics cover completed requests only
+    pub max_duration_reached: bool, // In-flight requests were dropped at max_duration_secs
+    pub circuit_breaker_tripped_after: Option<usize>, // Consecutive failures that stopped the test
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sla_p95_ms: Option<u64>, // Target the p95 response time is colored against
+}
+
+fn serialize_ms<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn serialize_opt_ms<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(d) => serialize_ms(d, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn print_report(
+    report: &TestReport,
+    output: OutputFormat,
+    unit: TimeUnit,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match output {
+        OutputFormat::Text => print_text_report(report, unit),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+        OutputFormat::Html => println!("{}", render_html_report(report, unit)),
+        OutputFormat::Prometheus => print!("{}", render_prometheus(report)),
+        // Every request was already printed as it completed
+        OutputFormat::Ndjson => {}
+    }
+    Ok(())
+}
+
+// One line of the ndjson stream for a completed request
+pub fn ndjson_record(result: &RequestResult) -> String {
+    serde_json::json!({
+        "index": result.index,
+        "status": result.status,
+        "elapsed_ms": result.elapsed.as_secs_f64() * 1000.0,
+        "ttfb_ms": result.time_to_first_byte.map(|d| d.as_secs_f64() * 1000.0),
+        "error": result.error,
+    })
+    .to_string()
+}
+
+pub fn write_html_report(
+    report: &TestReport,
+    file_path: &str,
+    unit: TimeUnit,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(file_path, render_html_report(report, unit))?;
+    Ok(())
+}
+
+// Replaces the file in one step, so a textfile collector never reads a partial file
+pub fn write_prometheus_textfile(
+    report: &TestReport,
+    file_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let temp_path = format!("{}.tmp", file_path);
+    std::fs::write(&temp_path, render_prometheus(report))?;
+    std::fs::rename(&temp_path, file_path)?;
+    Ok(())
+}
+
+// Writes one CSV row per completed request for offline analysis
+pub fn write_timings_csv(
+    results: &[RequestResult],
+    file_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    // Columns added after the first four go at the end, so readers that go by position keep
+    // working. The request_id column is only added when the requests carried one.
+    let with_ids = results.iter().any(|r| r.request_id.is_some());
+    let mut header = vec!["index", "status", "elapsed_ms", "error", "ttfb_ms"];
+    if with_ids {
+        header.push("request_id");
+    }
+    writer.write_record(header)?;
+
+    for result in results {
+        let mut record = vec![
+            result.index.to_string(),
+            result.status.map(|s| s.to_string()).unwrap_or_default(),
+            format!("{:.3}", result.elapsed.as_secs_f64() * 1000.0),
+            result.error.clone().unwrap_or_default(),
+            result
+                .time_to_first_byte
+                .map(|d| format!("{:.3}", d.as_secs_f64() * 1000.0))
+                .unwrap_or_default(),
+        ];
+        if with_ids {
+            record.push(result.request_id.clone().unwrap_or_default());
+        }
+        writer.write_record(record)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_text_report(report: &TestReport, unit: TimeUnit) {
+    if report.interrupted {
+        println!("Test interrupted, showing results for completed requests only");
+    }
+    if report.max_duration_reached {
+        println!("Stopped at max_duration_secs, requests still in flight are not counted");
+    }
+    if let Some(failures) = report.circuit_breaker_tripped_after {
+        println!(
+            "Circuit breaker tripped after {} consecutive failures",
+            failures
+        );
+    }
+    if report.total_requests == 0 {
+        println!("No requests were completed");
+        return;
+    }
+    let mut rows = vec![
+        row("Total Requests", report.total_requests),
+        row("Successful Requests", report.successful_requests),
+        row("Failed Requests", report.failed_requests),
+        row("Timeout Errors", report.timeout_errors),
+        row("Connection Errors", report.connection_errors),
+        row("HTTP Error Responses", report.http_errors),
+        row(
+            "Header Assertion Failures",
+            report.header_assertion_failures,
+        ),
+        row("Body Assertion Failures", report.body_assertion_failures),
+        row("Other Errors", report.other_errors),
+        row("Retried Requests", report.retried_requests),
+    ];
+    if report.dropped_requests > 0 {
+        rows.push(row("Dropped Requests", report.dropped_requests));
+    }
+    if report.oversize_responses > 0 {
+        rows.push(row("Oversize Responses", report.oversize_responses));
+    }
+    for (status, count) in &report.status_counts {
+        rows.push(row(&format!("Status {}", status), count));
+    }
+    // Empty classes are left out of the text report, the JSON report lists them all
+    for class in report.status_classes.iter().filter(|c| c.count > 0) {
+        let label = match class.class.as_str() {
+            "network error" => "Network Errors".to_string(),
+            class => format!("Status {}", class),
+        };
+        rows.push(row(
+            &label,
+            format!("{} ({:.2} %)", class.count, class.percentage),
+        ));
+    }
+    rows.push(row(
+        "Success Percentage",
+        format!("{:.2} %", report.success_percentage),
+    ));
+    rows.push((
+        "Failure Percentage".to_string(),
+        format!("{:.2} %", report.failure_percentage),
+        Some(report.failure_percentage == 0.0),
+    ));
+    rows.push(row(
+        "Average Response Time",
+        format_duration(report.average_response_time, unit),
+    ));
+    rows.push(row(
+        "Response Time Std Dev",
+        format_duration(report.std_dev_response_time, unit),
+    ));
+    rows.push(row(
+        "Minimum Response Time",
+        format_duration(report.min_response_time, unit),
+    ));
+    rows.push(row(
+        "Maximum Response Time",
+        format_duration(report.max_response_time, unit),
+    ));
+
+    let percentiles = [
+        (50, report.p50_response_time),
+        (90, report.p90_response_time),
+        (95, report.p95_response_time),
+        (99, report.p99_response_time),
+    ];
+    for (pct, value) in percentiles {
+        match (value, report.sla_p95_ms) {
+            (Some(d), Some(sla_ms)) if pct == 95 => rows.push((
+                format!("P{} Response Time (SLA {} ms)", pct, sla_ms),
+                format_duration(d, unit),
+                Some(d <= Duration::from_millis(sla_ms)),
+            )),
+            (value, _) => rows.push(row(
+                &format!("P{} Response Time", pct),
+                format_opt_duration(value, unit),
+            )),
+        }
+    }
+    rows.push(row(
+        "Average Time to First Byte",
+        format_duration(report.average_time_to_first_byte, unit),
+    ));
+    let first_byte_percentiles = [
+        (50, report.p50_time_to_first_byte),
+        (95, report.p95_time_to_first_byte),
+        (99, report.p99_time_to_first_byte),
+    ];
+    for (pct, value) in first_byte_percentiles {
+        rows.push(row(
+            &format!("P{} Time to First Byte", pct),
+            format_opt_duration(value, unit),
+        ));
+    }
+    rows.push(row("Throughput", format!("{:.2} req/s", report.throughput)));
+    rows.push(row(
+        "Total Response Size",
+        format!("{} B", report.total_response_bytes),
+    ));
+    rows.push(row(
+        "Minimum Response Size",
+        format!("{} B", report.min_response_bytes),
+    ));
+    rows.push(row(
+        "Maximum Response Size",
+        format!("{} B", report.max_response_bytes),
+    ));
+    rows.push(row(
+        "Average Response Size",
+        format!("{:.0} B", report.average_response_bytes),
+    ));
+    rows.push(row(
+        "Transfer Rate",
+        format!("{:.2} MB/s", report.transfer_rate),
+    ));
+    if let Some(ratio) = report.request_compression_ratio {
+        rows.push(row("Request Compression Ratio", format!("{:.2}x", ratio)));
+    }
+    rows.push(row("Seed", report.seed));
+    if let Some(ref version) = report.http_version {
+        rows.push(row("HTTP Version", version));
+    }
+    rows.push(row(
+        "Idle Connections per Host",
+        report
+            .pool_max_idle_per_host
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "unlimited".to_string()),
+    ));
+    rows.push(row(
+        "Idle Connection Timeout",
+        format!("{} s", report.pool_idle_timeout_secs),
+    ));
+    if let Some(ref metric) = report.metric {
+        let name = |stat: &str| format!("Metric {} {}", metric.path, stat);
+        rows.push(row(&name("Min"), metric.min));
+        rows.push(row(&name("Average"), format!("{:.2}", metric.average)));
+        rows.push(row(&name("Max"), metric.max));
+        rows.push(row(&name("P95"), metric.p95));
+        rows.push(row(&name("Values"), metric.count));
+        rows.push(row(&name("Missing"), metric.missing));
+    }
+    if let Some(ref apdex) = report.apdex {
+        rows.push(row(
+            &format!("Apdex ({} ms budget)", apdex.budget_ms),
+            format!("{:.2}", apdex.score),
+        ));
+        rows.push(row("Apdex Satisfied", apdex.satisfied));
+        rows.push(row("Apdex Tolerating", apdex.tolerating));
+        rows.push(row("Apdex Frustrated", apdex.frustrated));
+    }
+    print_table(&rows, "");
+
+    if !report.top_errors.is_empty() {
+        println!();
+        println!("Top Errors:");
+        for error in &report.top_errors {
+            println!("  {:>6}x {}", error.count, error.message);
+        }
+    }
+    print_text_histogram(report, unit);
+
+    if !report.traffic_profile.is_empty() {
+        println!();
+        println!("Traffic Profile:");
+        for bucket in &report.traffic_profile {
+            let window = format!("{}-{}s", bucket.start_secs, bucket.end_secs);
+            println!(
+                "  {:>12}  target {:.2} req/s, achieved {:.2} req/s",
+                window, bucket.target_rps, bucket.achieved_rps
+            );
+        }
+    }
+
+    for scenario in &report.scenarios {
+        print_group_report("Scenario", scenario, unit);
+    }
+    for url in &report.urls {
+        print_group_report("URL", url, unit);
+    }
+    for method in &report.methods {
+        print_group_report("Method", method, unit);
+    }
+    for stage in &report.stages {
+        print_group_report("Stage", stage, unit);
+    }
+    for group in &report.groups {
+        print_group_report("Group", group, unit);
+    }
+}
+
+// A table row without a color
+fn row(label: &str, value: impl ToString) -> (String, String, Option<bool>) {
+    (label.to_string(), value.to_string(), None)
+}
+
+// Prints a two-column table with the labels left-aligned and the values right-aligned. A
+// value is painted green or red when its row says whether it is good. Widths are measured
+// before painting, the escape codes take no space on screen.
+fn print_table(rows: &[(String, String, Option<bool>)], indent: &str) {
+    let color = use_color();
+    let label_width = rows.iter().map(|(label, _, _)| label.chars().count()).max();
+    let value_width = rows.iter().map(|(_, value, _)| value.chars().count()).max();
+
+    for (label, value, good) in rows {
+        let value_width = value_width.unwrap_or_default();
+        let value = format!("{:>value_width$}", value);
+        let value = match good {
+            Some(good) => paint(&value, *good, color),
+            None => value,
+        };
+        let label_width = label_width.unwrap_or_default();
+        println!("{}{:<label_width$}  {}", indent, label, value);
+    }
+}
+
+// Colors are only used on a terminal and can be turned off with NO_COLOR
+fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+// Green for a good value, red for a bad one
+fn paint(text: &str, good: bool, color: bool) -> String {
+    match (color, good) {
+        (false, _) => text.to_string(),
+        (true, true) => format!("\x1b[32m{}\x1b[0m", text),
+        (true, false) => format!("\x1b[31m{}\x1b[0m", text),
+    }
+}
+
+fn print_group_report(label: &str, group: &ScenarioReport, unit: TimeUnit) {
+    println!();
+    println!("{}: {}", label, group.name);
+    let rows = [
+        row("Total Requests", group.total_requests),
+        row("Successful Requests", group.successful_requests),
+        row("Failed Requests", group.failed_requests),
+        row(
+            "Average Response Time",
+            format_duration(group.average_response_time, unit),
+        ),
+        row(
+            "P95 Response Time",
+            format_opt_duration(group.p95_response_time, unit),
+        ),
+    ];
+    print_table(&rows, "  ");
+}
+
+// Buckets of 10ms, widened in 10ms steps so there are at most 20 rows, labeled in the unit
+fn print_text_histogram(report: &TestReport, unit: TimeUnit) {
+    const BAR_WIDTH: usize = 40;
+    const MAX_BUCKETS: u128 = 20;
+    let step = Duration::from_millis(10);
+
+    let times: Vec<Duration> = report.results.iter().map(|r| r.elapsed).collect();
+    let Some(max) = times.iter().max() else {
+        return;
+    };
+    let steps_per_bucket = (max.as_nanos() / step.as_nanos() / MAX_BUCKETS + 1) as u32;
+    let bucket_width = step * steps_per_bucket;
+
+    let buckets = histogram(&times, bucket_width);
+    let tallest = buckets.iter().copied().max().unwrap_or(1).max(1);
+    // Just enough decimals for the step, e.g. none in ms and two in seconds
+    let decimals = (-unit.convert(step).log10()).ceil().max(0.0) as usize;
+    let edge = |i: usize| format!("{:.*}", decimals, unit.convert(bucket_width * i as u32));
+
+    println!();
+    println!("Response Time Histogram:");
+    for (i, count) in buckets.iter().enumerate() {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(tallest));
+        println!(
+            "{:>7}-{:<7}{:<2} | {:<40} {}",
+            edge(i),
+            edge(i + 1),
+            unit.suffix(),
+            bar,
+            count
+        );
+    }
+}
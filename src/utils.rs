@@ -1,13 +1,151 @@
-use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::config::HttpMethod;
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-pub fn replace_placeholders(template: &str, data: &HashMap<String, String>) -> String {
-    let mut body = template.to_string();
-    for (key, value) in data {
-        body = body.replace(&format!("${{{}}}", key), value);
+use crate::{
+    config::HttpMethod,
+    data::Row,
+    report::{ApdexReport, RequestResult, StatusClassCount, TimeUnit},
+};
+
+// What a per-request random generator is used for, each gets an independent sequence
+pub const RNG_DATA: u64 = 1;
+pub const RNG_SCENARIO: u64 = 2;
+pub const RNG_THINK_TIME: u64 = 3;
+pub const RNG_GENERATORS: u64 = 4;
+pub const RNG_JITTER: u64 = 5;
+
+// Random generator for one purpose of one request. Deriving it from the seed and the
+// request index keeps seeded runs repeatable no matter how tasks are scheduled.
+pub fn request_rng(seed: u64, index: usize, purpose: u64) -> StdRng {
+    StdRng::seed_from_u64(
+        seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ purpose.wrapping_mul(0xC2B2_AE3D_27D4_EB4F),
+    )
+}
+
+// Replaces every ${column} whose column is in the row, or ${column.path} with a JSON path
+// into it such as ${user.name} or ${items[0].id}. Other placeholders are left unchanged.
+pub fn replace_placeholders(template: &str, data: &Row, rng: &mut StdRng) -> String {
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${") {
+        let token = &rest[start..];
+        let Some(end) = token.find('}') else {
+            break;
+        };
+        body.push_str(&rest[..start]);
+
+        match row_value(data, &token[2..end]) {
+            Some(value) => body.push_str(&value_text(value)),
+            None => body.push_str(&token[..=end]),
+        }
+        rest = &token[end + 1..];
+    }
+
+    body.push_str(rest);
+    resolve_generators(&body, rng)
+}
+
+// Value of a column, or of a JSON path into one. A column whose name contains dots, e.g.
+// one of a prefixed data file, is matched whole before its name is split into a path.
+pub fn row_value<'a>(row: &'a Row, path: &str) -> Option<&'a serde_json::Value> {
+    if let Some(value) = row.get(path) {
+        return Some(value);
+    }
+    path.char_indices()
+        .rev()
+        .filter(|(_, c)| *c == '.' || *c == '[')
+        .find_map(|(split, _)| json_path(row.get(&path[..split])?, &path[split..]))
+}
+
+// A data value as text: strings without quotes, null as nothing, objects and arrays as
+// compact JSON
+pub fn value_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+// Replaces ${__uuid}, ${__timestamp} and ${__random(min,max)} with a fresh value per occurrence.
+// ${__index} is not a generator, it is put into every data row instead.
+fn resolve_generators(template: &str, rng: &mut StdRng) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${__") {
+        let token = &rest[start..];
+        let Some(end) = token.find('}') else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+
+        match generate(&token[4..end], rng) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(&token[..=end]),
+        }
+        rest = &token[end + 1..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn generate(name: &str, rng: &mut StdRng) -> Option<String> {
+    match name {
+        "uuid" => Some(
+            uuid::Builder::from_random_bytes(rng.gen())
+                .into_uuid()
+                .to_string(),
+        ),
+        "timestamp" => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            Some(now.as_millis().to_string())
+        }
+        _ => {
+            let args = name.strip_prefix("random(")?.strip_suffix(')')?;
+            let (min, max) = args.split_once(',')?;
+            let min: i64 = min.trim().parse().ok()?;
+            let max: i64 = max.trim().parse().ok()?;
+            if min > max {
+                return None;
+            }
+            Some(rng.gen_range(min..=max).to_string())
+        }
+    }
+}
+
+// Looks up a value with a simple JSON path such as `$.data.token` or `items[0].id`.
+// Strings are returned without quotes, anything else as JSON text.
+pub fn extract_json_path(value: &serde_json::Value, path: &str) -> Option<String> {
+    match json_path(value, path)? {
+        serde_json::Value::Null => None,
+        value => Some(value_text(value)),
+    }
+}
+
+fn json_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut current = value;
+
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, indexes) = match segment.find('[') {
+            Some(bracket) => segment.split_at(bracket),
+            None => (segment, ""),
+        };
+        if !key.is_empty() {
+            current = current.get(key)?;
+        }
+        for index in indexes.split('[').filter(|s| !s.is_empty()) {
+            let index: usize = index.strip_suffix(']')?.parse().ok()?;
+            current = current.get(index)?;
+        }
     }
-    body
+    Some(current)
 }
 
 pub fn http_method_to_reqwest_method(method: &HttpMethod) -> reqwest::Method {
@@ -17,11 +155,388 @@ pub fn http_method_to_reqwest_method(method: &HttpMethod) -> reqwest::Method {
         HttpMethod::Put => reqwest::Method::PUT,
         HttpMethod::Delete => reqwest::Method::DELETE,
         HttpMethod::Patch => reqwest::Method::PATCH,
+        HttpMethod::Head => reqwest::Method::HEAD,
+        HttpMethod::Options => reqwest::Method::OPTIONS,
+    }
+}
+
+// Progress bar on stderr, or a spinner when the number of requests isn't known up front
+pub fn progress_bar(total: Option<usize>) -> ProgressBar {
+    let (bar, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total as u64),
+            "{spinner} [{elapsed_precise}] [{bar:40}] {pos}/{len} ETA {eta} {per_sec} {msg}  {prefix}",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{spinner} [{elapsed_precise}] {pos} requests {per_sec} {msg}  {prefix}",
+        ),
+    };
+    bar.set_style(
+        ProgressStyle::with_template(template)
+            .expect("invalid progress template")
+            .progress_chars("=> "),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+// Renders values as a unicode sparkline scaled to the largest one
+pub fn sparkline(values: &[usize]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or_default().max(1);
+    values
+        .iter()
+        .map(|&value| BARS[value * (BARS.len() - 1) / max])
+        .collect()
+}
+
+// Cuts text to at most `width` characters, marking the cut with an ellipsis
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+// Nearest-rank percentile over an ascending slice, the default value when it's empty
+pub fn percentile<T: Copy + Default>(sorted: &[T], pct: f64) -> T {
+    if sorted.is_empty() {
+        return T::default();
+    }
+
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Sample standard deviation of durations, zero for fewer than two samples
+pub fn std_dev(times: &[Duration]) -> Duration {
+    if times.len() < 2 {
+        return Duration::ZERO;
+    }
+
+    let secs: Vec<f64> = times.iter().map(Duration::as_secs_f64).collect();
+    let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+    let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (secs.len() - 1) as f64;
+    Duration::from_secs_f64(variance.sqrt())
+}
+
+// Apdex of the results against a latency budget. Requests within the budget are satisfied,
+// those within four times the budget tolerating, and slower or failed ones frustrated. The
+// score counts satisfied requests fully and tolerating ones half, from 0 to 1.
+pub fn apdex(results: &[RequestResult], budget: Duration) -> ApdexReport {
+    let mut satisfied = 0;
+    let mut tolerating = 0;
+    for result in results.iter().filter(|r| r.success) {
+        if result.elapsed <= budget {
+            satisfied += 1;
+        } else if result.elapsed <= budget * 4 {
+            tolerating += 1;
+        }
+    }
+
+    let score = if results.is_empty() {
+        0.0
+    } else {
+        (satisfied as f64 + tolerating as f64 / 2.0) / results.len() as f64
+    };
+    ApdexReport {
+        budget_ms: budget.as_millis() as u64,
+        satisfied,
+        tolerating,
+        frustrated: results.len() - satisfied - tolerating,
+        score,
+    }
+}
+
+// Rollup of the results by status class: 2xx to 5xx by the status' first digit, and requests
+// that got no response as network errors. Those five are always listed, in that order, and
+// any other class that occurred follows them.
+pub fn status_classes(results: &[RequestResult]) -> Vec<StatusClassCount> {
+    let mut counts: Vec<(String, usize)> = ["2xx", "3xx", "4xx", "5xx", "network error"]
+        .iter()
+        .map(|class| (class.to_string(), 0))
+        .collect();
+    for result in results {
+        let class = match result.status {
+            Some(status) => format!("{}xx", status / 100),
+            None => "network error".to_string(),
+        };
+        match counts.iter_mut().find(|(name, _)| *name == class) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((class, 1)),
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|(class, count)| StatusClassCount {
+            class,
+            count,
+            percentage: if results.is_empty() {
+                0.0
+            } else {
+                count as f64 / results.len() as f64 * 100.0
+            },
+        })
+        .collect()
+}
+
+// A duration in the unit at that unit's precision, e.g. 12.34 ms
+pub fn format_duration(duration: Duration, unit: TimeUnit) -> String {
+    format!(
+        "{:.*} {}",
+        unit.precision(),
+        unit.convert(duration),
+        unit.suffix()
+    )
+}
+
+// Like format_duration, with "N/A" for a statistic that has no value
+pub fn format_opt_duration(duration: Option<Duration>, unit: TimeUnit) -> String {
+    match duration {
+        Some(d) => format_duration(d, unit),
+        None => "N/A".to_string(),
     }
 }
 
-// Function to display progress
-pub fn display_progress(first: usize, second: usize) {
-    print!("\x1B[2J\x1B[1;1H");
-    println!("Progress: {first}/{second}");
+// Counts durations into consecutive buckets of `bucket_width` starting at zero
+pub fn histogram(times: &[Duration], bucket_width: Duration) -> Vec<usize> {
+    let Some(max) = times.iter().max() else {
+        return Vec::new();
+    };
+
+    let bucket_of = |d: &Duration| (d.as_nanos() / bucket_width.as_nanos().max(1)) as usize;
+    let mut buckets = vec![0; bucket_of(max) + 1];
+    for time in times {
+        buckets[bucket_of(time)] += 1;
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_of_empty_input_is_default() {
+        let empty: [u64; 0] = [];
+        assert_eq!(percentile(&empty, 50.0), 0);
+        assert_eq!(percentile(&[] as &[Duration], 99.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn percentile_of_one_element_is_that_element() {
+        for pct in [0.0, 1.0, 50.0, 99.0, 100.0] {
+            assert_eq!(percentile(&[7], pct), 7);
+        }
+    }
+
+    #[test]
+    fn percentile_100_is_the_maximum() {
+        assert_eq!(percentile(&[1, 2, 3, 4, 5], 100.0), 5);
+    }
+
+    #[test]
+    fn percentile_on_an_exact_rank_takes_that_rank() {
+        let values: Vec<u32> = (1..=10).collect();
+        // 50% of 10 is rank 5, 90% is rank 9, no rounding up to the next value
+        assert_eq!(percentile(&values, 50.0), 5);
+        assert_eq!(percentile(&values, 90.0), 9);
+        assert_eq!(percentile(&values, 10.0), 1);
+    }
+
+    #[test]
+    fn percentile_between_ranks_rounds_up() {
+        let values: Vec<u32> = (1..=10).collect();
+        assert_eq!(percentile(&values, 95.0), 10);
+        assert_eq!(percentile(&values, 51.0), 6);
+        // Rank 0 is clamped to the first element
+        assert_eq!(percentile(&values, 0.0), 1);
+    }
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&v| Duration::from_millis(v)).collect()
+    }
+
+    #[test]
+    fn histogram_of_no_times_has_no_buckets() {
+        assert!(histogram(&[], Duration::from_millis(10)).is_empty());
+    }
+
+    #[test]
+    fn histogram_counts_each_time_in_its_bucket() {
+        let times = ms(&[0, 5, 9, 10, 25]);
+        assert_eq!(histogram(&times, Duration::from_millis(10)), vec![3, 1, 1]);
+    }
+
+    #[test]
+    fn histogram_puts_bucket_edges_in_the_upper_bucket() {
+        let times = ms(&[10, 20]);
+        assert_eq!(histogram(&times, Duration::from_millis(10)), vec![0, 1, 1]);
+    }
+
+    #[test]
+    fn histogram_ends_at_the_slowest_time() {
+        let times = ms(&[1, 45]);
+        let buckets = histogram(&times, Duration::from_millis(10));
+        assert_eq!(buckets.len(), 5);
+        assert_eq!(buckets.iter().sum::<usize>(), 2);
+    }
+
+    fn result(status: Option<u16>, elapsed_ms: u64, success: bool) -> RequestResult {
+        RequestResult {
+            index: 0,
+            status,
+            elapsed: Duration::from_millis(elapsed_ms),
+            time_to_first_byte: None,
+            error: None,
+            success,
+            scenario: None,
+            scenario_index: None,
+            stage: None,
+            group: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn apdex_of_no_results_scores_zero() {
+        let report = apdex(&[], Duration::from_millis(100));
+        assert_eq!(report.score, 0.0);
+        assert_eq!(
+            (report.satisfied, report.tolerating, report.frustrated),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn apdex_sorts_results_by_the_budget() {
+        let results = [
+            result(Some(200), 50, true),
+            result(Some(200), 100, true), // On the budget is still satisfied
+            result(Some(200), 400, true), // Four times the budget is still tolerating
+            result(Some(200), 401, true),
+            result(Some(500), 10, false), // Failed requests are frustrated however fast
+        ];
+        let report = apdex(&results, Duration::from_millis(100));
+        assert_eq!(report.budget_ms, 100);
+        assert_eq!(
+            (report.satisfied, report.tolerating, report.frustrated),
+            (2, 1, 2)
+        );
+        assert_eq!(report.score, 0.5);
+    }
+
+    #[test]
+    fn apdex_counts_tolerating_requests_half() {
+        let results = [result(Some(200), 200, true), result(Some(200), 200, true)];
+        assert_eq!(apdex(&results, Duration::from_millis(100)).score, 0.5);
+    }
+
+    fn class_counts(results: &[RequestResult]) -> Vec<(String, usize)> {
+        status_classes(results)
+            .into_iter()
+            .map(|c| (c.class, c.count))
+            .collect()
+    }
+
+    #[test]
+    fn status_classes_list_every_class_when_empty() {
+        let classes = status_classes(&[]);
+        let names: Vec<&str> = classes.iter().map(|c| c.class.as_str()).collect();
+        assert_eq!(names, ["2xx", "3xx", "4xx", "5xx", "network error"]);
+        assert!(classes.iter().all(|c| c.count == 0 && c.percentage == 0.0));
+    }
+
+    #[test]
+    fn status_classes_group_by_first_digit() {
+        let results = [
+            result(Some(200), 1, true),
+            result(Some(204), 1, true),
+            result(Some(301), 1, true),
+            result(Some(404), 1, false),
+            result(Some(503), 1, false),
+            result(None, 1, false),
+            result(None, 1, false),
+            result(None, 1, false),
+        ];
+        assert_eq!(
+            class_counts(&results),
+            [
+                ("2xx".to_string(), 2),
+                ("3xx".to_string(), 1),
+                ("4xx".to_string(), 1),
+                ("5xx".to_string(), 1),
+                ("network error".to_string(), 3),
+            ]
+        );
+        let classes = status_classes(&results);
+        assert_eq!(classes[0].percentage, 25.0);
+        assert_eq!(classes[4].percentage, 37.5);
+    }
+
+    #[test]
+    fn status_classes_append_unusual_classes() {
+        let results = [result(Some(200), 1, true), result(Some(101), 1, false)];
+        let counts = class_counts(&results);
+        assert_eq!(counts.len(), 6);
+        assert_eq!(counts[5], ("1xx".to_string(), 1));
+    }
+
+    #[test]
+    fn status_classes_handle_many_distinct_codes() {
+        let results: Vec<RequestResult> = (200..600)
+            .map(|status| result(Some(status), 1, status < 400))
+            .collect();
+        let counts = class_counts(&results);
+        assert_eq!(counts.len(), 5);
+        assert!(counts[..4].iter().all(|(_, count)| *count == 100));
+    }
+
+    fn user_row() -> Row {
+        let value = serde_json::json!({
+            "user": {"name": "alice", "roles": ["admin", "dev"], "age": 30, "manager": null},
+            "product.sku": "A-1",
+        });
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn substituted(template: &str) -> String {
+        replace_placeholders(
+            template,
+            &user_row(),
+            &mut request_rng(0, 0, RNG_GENERATORS),
+        )
+    }
+
+    #[test]
+    fn nested_values_are_reachable_by_path() {
+        assert_eq!(substituted("${user.name}"), "alice");
+        assert_eq!(substituted("${user.roles[1]}"), "dev");
+        assert_eq!(substituted("${user.age}"), "30");
+        assert_eq!(substituted("${user.manager}"), "");
+    }
+
+    #[test]
+    fn objects_and_arrays_are_compact_json() {
+        assert_eq!(substituted("${user.roles}"), r#"["admin","dev"]"#);
+        let whole: serde_json::Value = serde_json::from_str(&substituted("${user}")).unwrap();
+        assert_eq!(whole, user_row()["user"]);
+    }
+
+    #[test]
+    fn dotted_column_names_are_matched_whole() {
+        assert_eq!(substituted("${product.sku}"), "A-1");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_unchanged() {
+        assert_eq!(
+            substituted("${missing} ${user.email} ${user.roles[5]} ${user"),
+            "${missing} ${user.email} ${user.roles[5]} ${user"
+        );
+    }
 }
@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+pub type Connection = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+// Why a WebSocket connection didn't succeed
+pub enum Failure {
+    Timeout,
+    Handshake(String),
+    Reply(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Timeout => write!(f, "timed out"),
+            Failure::Handshake(e) => write!(f, "handshake failed: {}", e),
+            Failure::Reply(e) => write!(f, "no reply: {}", e),
+        }
+    }
+}
+
+// Result of opening one connection. The connection is kept so the caller decides how long
+// it stays open.
+pub struct Outcome {
+    pub handshake: Duration,
+    pub status: Option<u16>,
+    pub failure: Option<Failure>,
+    pub connection: Option<Connection>,
+}
+
+pub fn is_websocket_url(url: &str) -> bool {
+    url.starts_with("ws://") || url.starts_with("wss://")
+}
+
+// Opens a connection and, when there is a message, sends it and waits for a reply
+pub async fn open(
+    url: &str,
+    headers: &[(String, String)],
+    message: Option<String>,
+    timeout: Option<Duration>,
+) -> Outcome {
+    let start = Instant::now();
+    let handshake = with_timeout(timeout, connect(url, headers)).await;
+    let elapsed = start.elapsed();
+
+    match handshake {
+        Ok((mut connection, status)) => {
+            let failure = match message {
+                Some(message) => with_timeout(timeout, exchange(&mut connection, message))
+                    .await
+                    .err(),
+                None => None,
+            };
+            Outcome {
+                handshake: elapsed,
+                status: Some(status),
+                failure,
+                connection: Some(connection),
+            }
+        }
+        Err(failure) => Outcome {
+            handshake: elapsed,
+            status: None,
+            failure: Some(failure),
+            connection: None,
+        },
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T, Failure>>,
+) -> Result<T, Failure> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or(Err(Failure::Timeout)),
+        None => future.await,
+    }
+}
+
+async fn connect(url: &str, headers: &[(String, String)]) -> Result<(Connection, u16), Failure> {
+    let handshake_error = |e: &dyn fmt::Display| Failure::Handshake(e.to_string());
+
+    let mut request = url.into_client_request().map_err(|e| handshake_error(&e))?;
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| handshake_error(&e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| handshake_error(&e))?;
+        request.headers_mut().insert(name, value);
+    }
+
+    let (connection, response) = connect_async(request)
+        .await
+        .map_err(|e| handshake_error(&e))?;
+    Ok((connection, response.status().as_u16()))
+}
+
+// Sends the message and waits for the first text or binary frame back
+async fn exchange(connection: &mut Connection, message: String) -> Result<(), Failure> {
+    connection
+        .send(Message::Text(message))
+        .await
+        .map_err(|e| Failure::Reply(e.to_string()))?;
+
+    while let Some(frame) = connection.next().await {
+        match frame.map_err(|e| Failure::Reply(e.to_string()))? {
+            Message::Text(_) | Message::Binary(_) => return Ok(()),
+            Message::Close(_) => break,
+            // Pings are answered by the library, nothing else counts as a reply
+            _ => {}
+        }
+    }
+    Err(Failure::Reply("connection closed".to_string()))
+}
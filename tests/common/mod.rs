@@ -0,0 +1,50 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+// Answers the n-th request, counting from 0, with `respond(n)`, then holds the connection
+// open for `hold` before reading the next request. Requests carry no body, so a request
+// ends at the blank line after its headers. Returns the URL and the number of requests so far.
+pub async fn start_server(
+    respond: fn(usize) -> &'static str,
+    hold: Duration,
+) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    let counter = Arc::clone(&requests);
+    tokio::spawn(async move {
+        while let Ok((mut connection, _)) = listener.accept().await {
+            let requests = Arc::clone(&counter);
+            tokio::spawn(async move {
+                let mut buffer = Vec::new();
+                let mut chunk = [0; 1024];
+                while let Ok(read) = connection.read(&mut chunk).await {
+                    if read == 0 {
+                        return;
+                    }
+                    buffer.extend_from_slice(&chunk[..read]);
+                    while let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                        buffer.drain(..end + 4);
+                        let n = requests.fetch_add(1, Ordering::SeqCst);
+                        if connection.write_all(respond(n).as_bytes()).await.is_err() {
+                            return;
+                        }
+                        tokio::time::sleep(hold).await;
+                    }
+                }
+            });
+        }
+    });
+    (url, requests)
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use common::start_server;
+use loadfire::{run_load_test, LoadTestConfig};
+
+// A recorded page load whose second request, say a missing image, fails with a 404
+#[tokio::test]
+async fn failed_har_request_does_not_stop_the_replay() {
+    let (url, requests) = start_server(
+        |n| match n {
+            1 => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n",
+            _ => "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+        },
+        Duration::ZERO,
+    )
+    .await;
+    let entries: Vec<_> = ["index.html", "missing.png", "app.js"]
+        .iter()
+        .map(|path| {
+            serde_json::json!({"request": {"method": "GET", "url": format!("{}{}", url, path)}})
+        })
+        .collect();
+    let path = std::env::temp_dir().join(format!("loadfire-{}.har", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        serde_json::json!({"log": {"entries": entries}}).to_string(),
+    )
+    .unwrap();
+
+    let config = LoadTestConfig {
+        har_file: Some(path.to_string_lossy().into_owned()),
+        request_count: Some(1),
+        progress: Some(false),
+        preflight: Some(false),
+        ..Default::default()
+    };
+    let report = run_load_test(config).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(report.successful_requests, 2);
+    assert_eq!(report.http_errors, 1);
+}
@@ -0,0 +1,50 @@
+use loadfire::{dry_run, run_load_test, LoadTestConfig};
+
+fn config() -> LoadTestConfig {
+    LoadTestConfig {
+        url: "http://127.0.0.1:9/".to_string(),
+        request_count: Some(1),
+        progress: Some(false),
+        preflight: Some(false),
+        ..Default::default()
+    }
+}
+
+// Each of these would panic on a division by zero or wait forever on a semaphore without
+// permits if it reached the executors
+#[tokio::test]
+async fn zero_rates_and_concurrency_are_rejected() {
+    let invalid = [
+        (
+            "rate_limit_rps",
+            LoadTestConfig {
+                rate_limit_rps: Some(0),
+                ..config()
+            },
+        ),
+        (
+            "arrival_rate_rps",
+            LoadTestConfig {
+                arrival_rate_rps: Some(0),
+                ..config()
+            },
+        ),
+        (
+            "concurrency",
+            LoadTestConfig {
+                concurrency: Some(0),
+                ..config()
+            },
+        ),
+    ];
+
+    for (name, config) in invalid {
+        let error = run_load_test(config.clone()).await.unwrap_err().to_string();
+        assert!(
+            error.contains(&format!("{} must be greater than 0", name)),
+            "{}",
+            error
+        );
+        assert!(dry_run(&config).await.is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use loadfire::{config::RatePoint, run_load_test, LoadTestConfig};
+
+const MAX_DURATION: Duration = Duration::from_secs(2);
+// Leeway for the requests in flight to be stopped and the report to be built
+const LEEWAY: Duration = Duration::from_millis(500);
+
+// Nothing listens on the discard port, so every request fails right away and only the
+// scheduling takes time
+fn config() -> LoadTestConfig {
+    LoadTestConfig {
+        url: "http://127.0.0.1:9/".to_string(),
+        max_duration_secs: Some(MAX_DURATION.as_secs()),
+        progress: Some(false),
+        preflight: Some(false),
+        ..Default::default()
+    }
+}
+
+// 20 requests at 2 per second would take 10s to schedule
+#[tokio::test]
+async fn max_duration_stops_the_arrival_rate_schedule() {
+    let config = LoadTestConfig {
+        arrival_rate_rps: Some(2),
+        request_count: Some(20),
+        ..config()
+    };
+
+    let start = Instant::now();
+    let report = run_load_test(config).await.unwrap();
+    assert!(
+        start.elapsed() < MAX_DURATION + LEEWAY,
+        "ran {:?}",
+        start.elapsed()
+    );
+    assert!(report.max_duration_reached);
+    assert!(report.total_requests < 20);
+}
+
+#[tokio::test]
+async fn max_duration_stops_the_traffic_profile() {
+    let config = LoadTestConfig {
+        traffic_profile: Some(vec![
+            RatePoint {
+                offset_secs: 0,
+                rps: 5,
+            },
+            RatePoint {
+                offset_secs: 10,
+                rps: 5,
+            },
+        ]),
+        ..config()
+    };
+
+    let start = Instant::now();
+    let report = run_load_test(config).await.unwrap();
+    assert!(
+        start.elapsed() < MAX_DURATION + LEEWAY,
+        "ran {:?}",
+        start.elapsed()
+    );
+    assert!(report.max_duration_reached);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::time::Duration;
+
+use common::start_server;
+use loadfire::{run_load_test, LoadTestConfig};
+
+fn config(url: String) -> LoadTestConfig {
+    LoadTestConfig {
+        url,
+        request_count: Some(1),
+        progress: Some(false),
+        preflight: Some(false),
+        ..Default::default()
+    }
+}
+
+// The headers promise 100 bytes but only 3 arrive before the timeout, whether the body is
+// only counted or read for an assertion
+#[tokio::test]
+async fn body_timeout_is_a_timeout_error() {
+    let (url, _) = start_server(
+        |_| "HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nabc",
+        Duration::from_secs(5),
+    )
+    .await;
+
+    for assert_body_contains in [None, Some("abc".to_string())] {
+        let config = LoadTestConfig {
+            timeout_ms: Some(300),
+            assert_body_contains,
+            ..config(url.clone())
+        };
+        let report = run_load_test(config).await.unwrap();
+        assert_eq!(report.successful_requests, 0);
+        assert_eq!(report.timeout_errors, 1);
+        assert_eq!(report.body_assertion_failures, 0);
+        assert_eq!(report.total_response_bytes, 0);
+    }
+}
+
+// The first attempt fails with a 503 and the retry comes after a 400ms backoff, which
+// belongs to the response time but not to the time to first byte
+#[tokio::test]
+async fn time_to_first_byte_leaves_out_retries() {
+    let (url, _) = start_server(
+        |n| match n {
+            0 => "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+            _ => "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok",
+        },
+        Duration::ZERO,
+    )
+    .await;
+    let config = LoadTestConfig {
+        retries: Some(1),
+        retry_backoff_ms: Some(400),
+        ..config(url)
+    };
+
+    let report = run_load_test(config).await.unwrap();
+    assert_eq!(report.successful_requests, 1);
+    let result = &report.results[0];
+    assert!(result.elapsed >= Duration::from_millis(400));
+    let first_byte = result.time_to_first_byte.unwrap();
+    assert!(first_byte < Duration::from_millis(200), "{:?}", first_byte);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{collections::HashMap, sync::atomic::Ordering, time::Duration};
+
+use common::start_server;
+use loadfire::{run_load_test, LoadTestConfig};
+
+fn config(url: String) -> LoadTestConfig {
+    LoadTestConfig {
+        url,
+        request_count: Some(1),
+        retries: Some(2),
+        retry_backoff_ms: Some(10),
+        progress: Some(false),
+        preflight: Some(false),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn connection_errors_are_retried() {
+    // Nothing listens on the discard port
+    let report = run_load_test(config("http://127.0.0.1:9/".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(report.connection_errors, 1);
+    assert_eq!(report.retried_requests, 1);
+}
+
+#[tokio::test]
+async fn server_errors_are_retried() {
+    let (url, requests) = start_server(
+        |_| "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+        Duration::ZERO,
+    )
+    .await;
+    let report = run_load_test(config(url)).await.unwrap();
+    assert_eq!(report.http_errors, 1);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+// Retrying a server that is too slow to answer would only add to its load
+#[tokio::test]
+async fn timeouts_are_not_retried() {
+    let (url, requests) = start_server(|_| "", Duration::from_secs(5)).await;
+    let config = LoadTestConfig {
+        timeout_ms: Some(200),
+        ..config(url)
+    };
+    let report = run_load_test(config).await.unwrap();
+    assert_eq!(report.timeout_errors, 1);
+    assert_eq!(report.retried_requests, 0);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+// A header value with a line break fails the same way on every attempt
+#[tokio::test]
+async fn invalid_requests_are_not_retried() {
+    let (url, requests) = start_server(
+        |_| "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+        Duration::ZERO,
+    )
+    .await;
+    let config = LoadTestConfig {
+        headers: Some(HashMap::from([(
+            "X-Broken".to_string(),
+            "a\nb".to_string(),
+        )])),
+        ..config(url)
+    };
+    let report = run_load_test(config).await.unwrap();
+    assert_eq!(report.other_errors, 1);
+    assert_eq!(report.retried_requests, 0);
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
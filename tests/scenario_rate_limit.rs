@@ -0,0 +1,121 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use loadfire::{
+    config::{HttpMethod, Scenario},
+    run_load_test, LoadTestConfig,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+// Request methods in the order they arrived, with the time they arrived
+type Arrivals = Arc<Mutex<Vec<(String, Instant)>>>;
+
+// Answers every request with an empty 200 and records its method. Requests carry no body,
+// so a request ends at the blank line after its headers.
+async fn start_server() -> (String, Arrivals) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/items", listener.local_addr().unwrap());
+    let arrivals: Arrivals = Arc::default();
+
+    let recorded = Arc::clone(&arrivals);
+    tokio::spawn(async move {
+        while let Ok((mut connection, _)) = listener.accept().await {
+            let recorded = Arc::clone(&recorded);
+            tokio::spawn(async move {
+                let mut buffer = Vec::new();
+                let mut chunk = [0; 1024];
+                while let Ok(read) = connection.read(&mut chunk).await {
+                    if read == 0 {
+                        return;
+                    }
+                    buffer.extend_from_slice(&chunk[..read]);
+                    while let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&buffer[..end]).into_owned();
+                        buffer.drain(..end + 4);
+                        let method = head.split(' ').next().unwrap_or_default().to_string();
+                        recorded.lock().unwrap().push((method, Instant::now()));
+                        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                        if connection.write_all(response).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (url, arrivals)
+}
+
+fn scenario(url: &str, method: HttpMethod, weight: u32, rate_limit_rps: Option<u32>) -> Scenario {
+    Scenario {
+        name: None,
+        url: url.to_string(),
+        method: Some(method),
+        headers: None,
+        body: None,
+        weight,
+        rate_limit_rps,
+    }
+}
+
+// Two unnamed scenarios on the same URL, only the writes capped: the writes must keep to
+// their rate while the reads are sent right away
+#[tokio::test]
+async fn scenario_rate_limit_only_caps_its_own_scenario() {
+    const WRITE_RPS: u32 = 5;
+    let (url, arrivals) = start_server().await;
+    let config = LoadTestConfig {
+        request_count: Some(40),
+        seed: Some(1),
+        progress: Some(false),
+        preflight: Some(false),
+        scenarios: Some(vec![
+            scenario(&url, HttpMethod::Get, 3, None),
+            scenario(&url, HttpMethod::Post, 1, Some(WRITE_RPS)),
+        ]),
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let report = run_load_test(config).await.unwrap();
+    assert_eq!(report.successful_requests, 40);
+
+    let arrivals = arrivals.lock().unwrap();
+    let reads: Vec<Duration> = arrivals
+        .iter()
+        .filter(|(method, _)| method == "GET")
+        .map(|(_, at)| at.duration_since(start))
+        .collect();
+    let writes: Vec<Duration> = arrivals
+        .iter()
+        .filter(|(method, _)| method == "POST")
+        .map(|(_, at)| at.duration_since(start))
+        .collect();
+    assert!(writes.len() >= 2, "too few writes to check the rate");
+
+    // The n-th write is released n periods after the start at the earliest
+    let period = Duration::from_secs(1) / WRITE_RPS;
+    let mut writes_sorted = writes.clone();
+    writes_sorted.sort();
+    for (n, at) in writes_sorted.iter().enumerate() {
+        assert!(
+            *at >= period * (n as u32 + 1) - Duration::from_millis(20),
+            "write {} arrived after {:?}, before its slot",
+            n + 1,
+            at
+        );
+    }
+
+    // Throttled together with the writes, the reads would take as long as they do
+    let last_read = reads.iter().max().unwrap();
+    assert!(
+        *last_read < period * 2,
+        "reads were held back until {:?}",
+        last_read
+    );
+}